@@ -0,0 +1,19 @@
+# man-with key binding for bash (4.0 or later).
+#
+#   eval "$(man-with init bash)"
+#
+# Alt-h opens man-with with the command being typed and puts the composed
+# command line back into the editing buffer.
+
+__man_with_widget() {
+    local line
+    [[ $READLINE_LINE = *[![:space:]]* ]] || return
+
+    line=$(command man-with --print --shell --line "$READLINE_LINE" < /dev/tty) || return
+    if [ -n "$line" ]; then
+        READLINE_LINE=$line
+        READLINE_POINT=${#line}
+    fi
+}
+
+bind -x '"\eh": __man_with_widget'
@@ -0,0 +1,18 @@
+# man-with key binding for fish.
+#
+#   man-with init fish | source
+#
+# Alt-h opens man-with with the command being typed and puts the composed
+# command line back into the editing buffer.
+
+function __man_with_widget
+    set -l buffer (commandline | string collect)
+    string match -qr '\S' -- $buffer; or return
+
+    set -l line (command man-with --print --shell --line $buffer < /dev/tty)
+    and test -n "$line"
+    and commandline -r -- $line
+    commandline -f repaint
+end
+
+bind \eh __man_with_widget
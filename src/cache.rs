@@ -0,0 +1,154 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::help::Help;
+use crate::man::Line;
+
+// Identifies what a page was rendered from. An entry is only used while the
+// man file or binary it came from keeps the same modification time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Key {
+    version: String,
+    source: String,
+    page: String,
+    width: usize,
+    path: PathBuf,
+    modified: (u64, u32),
+}
+
+impl Key {
+    pub fn new(source: &str, page: &str, width: usize, path: &Path) -> Option<Self> {
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?;
+
+        Some(Key {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            source: source.to_string(),
+            page: page.to_string(),
+            width,
+            path: path.to_path_buf(),
+            modified: (modified.as_secs(), modified.subsec_nanos()),
+        })
+    }
+
+    fn file_name(&self) -> String {
+        let page = self
+            .page
+            .chars()
+            .map(|c| match c {
+                c if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+
+        format!("{}-{}.json", self.source, page)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub key: Key,
+    pub lines: Vec<Line>,
+    pub help: Help,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    // `$XDG_CACHE_HOME/man-with`, or the platform's equivalent.
+    pub fn open() -> Option<Self> {
+        dirs::cache_dir().map(|dir| Cache::with_dir(dir.join("man-with")))
+    }
+
+    pub fn with_dir<P: Into<PathBuf>>(dir: P) -> Self {
+        Cache { dir: dir.into() }
+    }
+
+    pub fn read(&self, key: &Key) -> Option<Entry> {
+        let file = File::open(self.dir.join(key.file_name())).ok()?;
+        let entry: Entry = serde_json::from_reader(BufReader::new(file)).ok()?;
+
+        if entry.key == *key {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    pub fn write(&self, entry: &Entry) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first so that a concurrent reader never
+        // sees half an entry.
+        let path = self.dir.join(entry.key.file_name());
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut file, entry)?;
+        }
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+}
+
+// Looks `command` up in `$PATH` like a shell would.
+pub fn which(command: &str) -> Option<PathBuf> {
+    if command.contains('/') {
+        return Some(PathBuf::from(command)).filter(|p| p.is_file());
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(command))
+            .find(|p| p.is_file())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::man::LineKind;
+
+    #[test]
+    fn invalidated_by_mtime() {
+        let dir = env::temp_dir().join(format!("man-with-cache-{}", std::process::id()));
+        let source = dir.join("ls.1");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&source, ".TH LS 1").unwrap();
+
+        let cache = Cache::with_dir(dir.join("cache"));
+        let key = Key::new("man", "ls", 80, &source).unwrap();
+        let entry = Entry {
+            key: key.clone(),
+            lines: vec![Line::new(
+                "ls".chars().map(|c| (c, crate::man::Font::Bold)),
+                LineKind::Text,
+            )],
+            help: Help::default(),
+        };
+        cache.write(&entry).unwrap();
+
+        let hit = cache.read(&key).unwrap();
+        assert_eq!("ls", hit.lines[0].text);
+        assert_eq!(entry.lines[0].spans, hit.lines[0].spans);
+
+        let mut touched = key.clone();
+        touched.modified.0 += 1;
+        assert!(cache.read(&touched).is_none());
+        assert!(cache.read(&Key { width: 100, ..key }).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
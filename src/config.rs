@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+use serde_derive::Deserialize;
+use toml::Value;
+
+use crate::history::Limits;
+use crate::keymap::Keymap;
+
+const LOCAL: &str = ".man-with.toml";
+const NAMES: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// A color name (`red`, `bright-red`), a 256 color palette index or `#rrggbb`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Color {
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    pub fn fg(self) -> String {
+        match self {
+            Color::Ansi(n) => termion::color::Fg(termion::color::AnsiValue(n)).to_string(),
+            Color::Rgb(r, g, b) => termion::color::Fg(termion::color::Rgb(r, g, b)).to_string(),
+        }
+    }
+
+    pub fn bg(self) -> String {
+        match self {
+            Color::Ansi(n) => termion::color::Bg(termion::color::AnsiValue(n)).to_string(),
+            Color::Rgb(r, g, b) => termion::color::Bg(termion::color::Rgb(r, g, b)).to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let name = s.to_lowercase();
+        let (bright, base) = match name
+            .strip_prefix("bright-")
+            .or_else(|| name.strip_prefix("light-"))
+        {
+            Some(base) => (8, base),
+            None => (0, name.as_str()),
+        };
+        if let Some(n) = NAMES.iter().position(|c| *c == base) {
+            return Ok(Color::Ansi(n as u8 + bright));
+        }
+        if let Ok(n) = name.parse::<u8>() {
+            return Ok(Color::Ansi(n));
+        }
+
+        let hex = name
+            .strip_prefix('#')
+            .filter(|h| h.len() == 6)
+            .and_then(|h| u32::from_str_radix(h, 16).ok());
+        match hex {
+            Some(rgb) => Ok(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+            None => Err(format!(
+                "invalid color `{}`, expected a name such as `red` or `bright-red`, 0-255 or #rrggbb",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    // The command line being edited.
+    pub command: Color,
+    // The completion shown after the cursor.
+    pub hint: Color,
+    // Occurrences of the argument in the page.
+    pub highlight: Color,
+    // The selected line of menus and file lists.
+    pub selected: Color,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            command: Color::Ansi(7),
+            hint: Color::Ansi(4),
+            highlight: Color::Ansi(1),
+            selected: Color::Ansi(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub prompt: String,
+    // Lines of the page viewer.
+    pub size: Option<usize>,
+    pub history: Option<PathBuf>,
+    // Recall command lines that exited with a failure too.
+    pub history_failed: bool,
+    // Entries kept in the history file, and for how many days.
+    pub history_max_entries: usize,
+    pub history_max_age: Option<u64>,
+    pub colors: Colors,
+    // Key sequences such as `C-x C-e` mapped to action names, or "none".
+    pub keys: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            prompt: "> ".to_string(),
+            size: None,
+            history: None,
+            history_failed: false,
+            history_max_entries: Limits::default().max_entries,
+            history_max_age: None,
+            colors: Colors::default(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn history_limits(&self) -> Limits {
+        Limits {
+            max_entries: self.history_max_entries,
+            max_age: self.history_max_age.map(|days| days * 24 * 60 * 60),
+        }
+    }
+
+    // `$XDG_CONFIG_HOME/man-with/config.toml`, overridden key by key by the
+    // nearest `.man-with.toml` in `dir` or one of its parents.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let global = dirs::config_dir().map(|d| d.join("man-with").join("config.toml"));
+        let local = dir.ancestors().map(|d| d.join(LOCAL)).find(|p| p.is_file());
+
+        Self::read(global.iter().chain(local.iter()).filter(|p| p.is_file()))
+    }
+
+    pub fn read<'a, I>(paths: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = &'a PathBuf>,
+    {
+        let mut merged = Value::Table(Default::default());
+
+        for path in paths {
+            let parse = |e: &dyn fmt::Display| ConfigError::Parse(path.clone(), e.to_string());
+            let text = fs::read_to_string(path).map_err(|e| parse(&e))?;
+            let mut value = text.parse::<Value>().map_err(|e| parse(&e))?;
+            // Checked file by file so that errors name the file at fault.
+            let config = value.clone().try_into::<Config>().map_err(|e| parse(&e))?;
+            Keymap::new(&config.keys).map_err(|e| parse(&e))?;
+            if config.size == Some(0) {
+                return Err(parse(&"`size` must be at least 1").into());
+            }
+
+            if let Some(Value::String(history)) = value.get_mut("history") {
+                let expanded = PathBuf::from(shellexpand::tilde(history.as_str()).as_ref());
+                let base = path.parent().unwrap_or_else(|| Path::new("."));
+                *history = base.join(expanded).to_string_lossy().to_string();
+            }
+            merge(&mut merged, value);
+        }
+
+        Ok(merged.try_into()?)
+    }
+}
+
+fn merge(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Table(base), Value::Table(table)) => {
+            for (key, value) in table {
+                match base.get_mut(&key) {
+                    Some(b) => merge(b, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_overrides_global() {
+        let dir = std::env::temp_dir().join(format!("man-with-config-{}", std::process::id()));
+        let project = dir.join("project");
+        fs::create_dir_all(&project).unwrap();
+        let global = dir.join("config.toml");
+        let local = project.join(LOCAL);
+        fs::write(
+            &global,
+            "prompt = \"$ \"\nsize = 20\n[colors]\nhint = \"green\"\nselected = \"#ff8000\"\n",
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            "history = \"history\"\n[colors]\nhint = \"bright-cyan\"\n",
+        )
+        .unwrap();
+
+        let config = Config::read(&[global.clone(), local.clone()]).unwrap();
+        assert_eq!("$ ", config.prompt);
+        assert_eq!(Some(20), config.size);
+        assert_eq!(Some(project.join("history")), config.history);
+        assert_eq!(Color::Ansi(14), config.colors.hint);
+        assert_eq!(Color::Rgb(255, 128, 0), config.colors.selected);
+        assert_eq!(Color::Ansi(1), config.colors.highlight);
+
+        fs::write(&local, "[colors]\nhint = \"chartreuse\"\n").unwrap();
+        let e = Config::read(&[global, local.clone()])
+            .unwrap_err()
+            .to_string();
+        assert!(e.starts_with(&format!("{}: invalid color `chartreuse`", local.display())));
+
+        fs::write(&local, "[keys]\n\"C-x C-e\" = \"edit\"\n").unwrap();
+        let e = Config::read(std::slice::from_ref(&local))
+            .unwrap_err()
+            .to_string();
+        assert!(e.ends_with("unknown action `edit` for `C-x C-e`"));
+
+        fs::write(&local, "size = 0\n").unwrap();
+        let e = Config::read(std::slice::from_ref(&local))
+            .unwrap_err()
+            .to_string();
+        assert!(e.ends_with("`size` must be at least 1"));
+
+        fs::write(&local, "colour = \"red\"\n").unwrap();
+        let e = Config::read(&[local]).unwrap_err().to_string();
+        assert!(e.contains("unknown field `colour`"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,25 +1,12 @@
+use crate::help::Help;
+use crate::keymap::Action;
+use crate::man::Line;
+
 pub enum Event {
     Key(char),
     ReadLine(String),
+    Source(usize, Vec<Line>),
+    Loaded(usize, Help),
     Candidate(super::ui::prompt::PromptMode),
-    Enter,
-    Backspace,
-    Delete,
-    History,
-    Tab,
-    Forward,
-    Back,
-    Next,
-    Prev,
-    Up,
-    Down,
-    Left,
-    Right,
-    Quit,
-
-    MoveTo(i32),
-
-    Fn1,
-    Fn2,
-    Fn3,
+    Action(Action),
 }
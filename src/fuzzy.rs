@@ -0,0 +1,176 @@
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 16;
+const GAP: i64 = 1;
+const LEADING_GAP: i64 = 1;
+const START: i64 = 12;
+const WORD_START: i64 = 8;
+const DASH: i64 = 10;
+const PATH: i64 = 12;
+const CASE: i64 = 1;
+
+// Scores `text` as a subsequence match of `pattern`, or `None` when some
+// character of the pattern is missing. Matches at the start of words,
+// after `-`/`--` and after path separators are worth more, as are runs of
+// consecutive characters. The pattern is case sensitive only when it
+// contains an upper case letter.
+pub fn score(pattern: &str, text: &str) -> Option<i64> {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    if pattern.len() > text.len() {
+        return None;
+    }
+
+    let smart_case = pattern.iter().any(|c| c.is_uppercase());
+    let eq = |p: char, t: char| {
+        if smart_case {
+            p == t
+        } else {
+            p.to_lowercase().eq(t.to_lowercase())
+        }
+    };
+
+    let bonus = text
+        .iter()
+        .enumerate()
+        .map(|(j, c)| {
+            let prev = if j == 0 { None } else { Some(text[j - 1]) };
+            match prev {
+                None => START,
+                Some('/') => PATH,
+                Some('-') if *c != '-' => DASH,
+                Some(p) if !p.is_alphanumeric() && c.is_alphanumeric() => WORD_START,
+                Some(p) if p.is_lowercase() && c.is_uppercase() => WORD_START,
+                _ => 0,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // best[j]: best score with the current pattern character matched at j.
+    let mut best = vec![None; text.len()];
+    for (i, p) in pattern.iter().enumerate() {
+        let mut next = vec![None; text.len()];
+        let mut run: Option<i64> = None;
+
+        for j in 0..text.len() {
+            let s = if eq(*p, text[j]) {
+                let mut s = MATCH + bonus[j];
+                if *p == text[j] {
+                    s += CASE;
+                }
+
+                if i == 0 {
+                    Some(s - LEADING_GAP * j as i64)
+                } else {
+                    let consecutive = j
+                        .checked_sub(1)
+                        .and_then(|k| best[k])
+                        .map(|b: i64| b + s + CONSECUTIVE);
+                    let gapped = run.map(|r| r + s);
+                    consecutive.max(gapped)
+                }
+            } else {
+                None
+            };
+
+            // Skipping characters between two matches costs a little.
+            if j > 0 {
+                run = run.map(|r| r - GAP).max(best[j - 1]);
+            }
+            next[j] = s;
+        }
+        best = next;
+    }
+
+    best.into_iter().flatten().max()
+}
+
+// Keeps the items matching `pattern`, best first. Equal scores keep the
+// shorter text first and otherwise the original order.
+pub fn rank<T, F>(pattern: &str, items: Vec<T>, text: F) -> Vec<T>
+where
+    F: Fn(&T) -> &str,
+{
+    rank_scored(pattern, items, text)
+        .into_iter()
+        .map(|(_, item)| item)
+        .collect()
+}
+
+// `rank` with the scores kept, so that more items can be merged in later.
+pub fn rank_scored<T, F>(pattern: &str, items: Vec<T>, text: F) -> Vec<(i64, T)>
+where
+    F: Fn(&T) -> &str,
+{
+    let mut scored = items
+        .into_iter()
+        .filter_map(|item| score(pattern, text(&item)).map(|s| (s, item)))
+        .collect::<Vec<_>>();
+
+    scored.sort_by(|x, y| order(x, y, &text));
+    scored
+}
+
+fn order<T, F>((a, x): &(i64, T), (b, y): &(i64, T), text: &F) -> std::cmp::Ordering
+where
+    F: Fn(&T) -> &str,
+{
+    b.cmp(a).then(text(x).len().cmp(&text(y).len()))
+}
+
+// Merges two results of `rank_scored` into the order ranking both at once
+// would give, the items of `ranked` first among equals.
+pub fn merge<T, F>(ranked: Vec<(i64, T)>, more: Vec<(i64, T)>, text: F) -> Vec<(i64, T)>
+where
+    F: Fn(&T) -> &str,
+{
+    let mut merged = Vec::with_capacity(ranked.len() + more.len());
+    let mut more = more.into_iter().peekable();
+
+    for item in ranked {
+        while let Some(m) = more.next_if(|m| order(m, &item, &text).is_lt()) {
+            merged.push(m);
+        }
+        merged.push(item);
+    }
+    merged.extend(more);
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn subsequence() {
+        assert!(score("gc", "git-commit").is_some());
+        assert!(score("cg", "git-commit").is_none());
+        assert_eq!(Some(0), score("", "anything"));
+    }
+
+    #[test]
+    fn boundaries_score_higher() {
+        assert!(score("al", "--all").unwrap() > score("al", "--ball").unwrap());
+        assert!(score("mc", "src/main.c").unwrap() > score("mc", "srcmxc").unwrap());
+        assert!(score("col", "--color").unwrap() > score("col", "--c-o-l").unwrap());
+    }
+
+    #[test]
+    fn ranking() {
+        let items = vec!["--all-namespaces", "--almost-all", "--all", "--label"];
+        assert_eq!(
+            vec!["--all", "--almost-all", "--all-namespaces", "--label"],
+            rank("al", items.clone(), |s| s)
+        );
+
+        let first = rank_scored("al", items[..2].to_vec(), |s| s);
+        let rest = rank_scored("al", items[2..].to_vec(), |s| s);
+        let merged = merge(first, rest, |s| s);
+        assert_eq!(
+            rank("al", items, |s| s),
+            merged.into_iter().map(|(_, s)| s).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::options::Options;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Subcommand {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Help {
+    pub usage: Vec<String>,
+    pub options: Options,
+    pub subcommands: Vec<Subcommand>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    None,
+    Usage,
+    Commands,
+    Arguments,
+    Other,
+}
+
+impl Help {
+    // Understands the layouts of clap, argparse, Go's flag and cobra,
+    // getopt-style GNU tools and docopt. Options are recognised anywhere,
+    // subcommands only below a heading that mentions commands.
+    pub fn parse(lines: &[String]) -> Self {
+        let mut help = Help {
+            options: Options::parse(lines),
+            ..Help::default()
+        };
+        let mut section = Section::None;
+        let mut choices: Vec<String> = Vec::new();
+        let mut column = None;
+
+        for (n, line) in lines.iter().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                if section == Section::Usage {
+                    section = Section::None;
+                }
+                continue;
+            }
+
+            if let Some((next, rest)) = heading(line, section) {
+                section = next;
+                if section == Section::Usage && !rest.is_empty() {
+                    help.usage.push(rest.to_string());
+                }
+                continue;
+            }
+
+            match section {
+                Section::Usage => help.usage.push(trimmed.to_string()),
+                Section::Commands => match entry(line) {
+                    Some((names, description, start)) => {
+                        column = Some(start).filter(|_| !description.is_empty());
+                        help.push(names, description, n);
+                    }
+                    None if column.map(|c| indent_of(line) >= c) == Some(true) => {
+                        help.continue_description(trimmed)
+                    }
+                    None => column = None,
+                },
+                Section::Arguments => {
+                    // argparse lists subparsers as `{add,rm}` followed by
+                    // an indented line for each of them.
+                    if trimmed.starts_with('{') {
+                        if let Some(end) = trimmed.find('}') {
+                            choices = trimmed[1..end].split(',').map(String::from).collect();
+                            for name in &choices {
+                                help.push(vec![name.clone()], String::new(), n);
+                            }
+                        }
+                    } else if let Some((names, description, _)) = entry(line) {
+                        if choices.contains(&names[0]) {
+                            if let Some(sub) =
+                                help.subcommands.iter_mut().find(|s| s.name == names[0])
+                            {
+                                sub.description = description;
+                                sub.line = n;
+                            }
+                        }
+                    }
+                }
+                Section::None | Section::Other => {}
+            }
+        }
+
+        help
+    }
+
+    pub fn subcommand(&self, name: &str) -> Option<&Subcommand> {
+        self.subcommands
+            .iter()
+            .find(|s| s.name == name || s.aliases.iter().any(|a| a == name))
+    }
+
+    fn push(&mut self, mut names: Vec<String>, description: String, line: usize) {
+        let name = names.remove(0);
+        if self.subcommand(&name).is_some() {
+            return;
+        }
+        self.subcommands.push(Subcommand {
+            name,
+            aliases: names,
+            description,
+            line,
+        });
+    }
+
+    fn continue_description(&mut self, text: &str) {
+        if let Some(sub) = self.subcommands.last_mut() {
+            if !sub.description.is_empty() {
+                sub.description.push(' ');
+                sub.description.push_str(text);
+            }
+        }
+    }
+}
+
+fn heading(line: &str, current: Section) -> Option<(Section, &str)> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let lower = line.to_lowercase();
+    if lower.starts_with("usage of") {
+        // Go's flag package: `Usage of prog:` directly followed by flags.
+        return Some((Section::Other, ""));
+    }
+    if lower.starts_with("usage") {
+        let rest = line.find(':').map(|n| line[n + 1..].trim()).unwrap_or("");
+        return Some((Section::Usage, rest));
+    }
+    if !line.ends_with(':') && !line.chars().all(|c| !c.is_lowercase()) {
+        // git groups its commands below lowercase captions such as
+        // `start a working area (see also: git help tutorial)`.
+        return if current == Section::Commands {
+            Some((Section::Commands, ""))
+        } else {
+            Some((Section::Other, ""))
+        };
+    }
+
+    let section = if lower.contains("command") {
+        Section::Commands
+    } else if lower.contains("argument") || lower.starts_with("args") {
+        Section::Arguments
+    } else {
+        Section::Other
+    };
+    Some((section, ""))
+}
+
+fn indent_of(line: &str) -> usize {
+    line.chars().take_while(|c| c.is_whitespace()).count()
+}
+
+// Splits `  build, b    Compile the current package` into its names, the
+// description and the column the description starts at.
+fn entry(line: &str) -> Option<(Vec<String>, String, usize)> {
+    let indent = indent_of(line);
+    let trimmed = line.trim_start();
+    let (names, description, column) = match trimmed.find("  ") {
+        Some(n) => {
+            let rest = &trimmed[n..];
+            (&trimmed[..n], rest.trim(), indent + n + indent_of(rest))
+        }
+        None => (trimmed.trim_end(), "", 0),
+    };
+
+    let names = names
+        .split(',')
+        .map(|n| n.trim().to_string())
+        .collect::<Vec<_>>();
+    let valid = names.iter().all(|n| {
+        n.chars().next().map(|c| c.is_alphanumeric()) == Some(true)
+            && n.chars().next_back().map(|c| c.is_alphanumeric()) == Some(true)
+            && n.chars().all(|c| c.is_alphanumeric() || "-_:.".contains(c))
+    });
+
+    if valid {
+        Some((names, description.to_string(), column))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn clap_help() {
+        let help = Help::parse(&lines(
+            "Usage: cargo [OPTIONS] [COMMAND]\n\nOptions:\n  -v, --verbose...        Use verbose output\n      --color <WHEN>      Coloring [possible values: auto, always, never]\n  -j, --jobs <N>          Number of jobs [default: 4]\n\nCommands:\n    build, b    Compile the current package\n    clean       Remove the target directory\n                and its contents\n",
+        ));
+
+        assert_eq!(vec!["cargo [OPTIONS] [COMMAND]".to_string()], help.usage);
+        assert!(help.options.find("-v").is_some());
+
+        let color = help.options.find("--color").unwrap();
+        assert_eq!(Some("<WHEN>".to_string()), color.argument);
+        assert_eq!(vec!["auto", "always", "never"], color.values);
+        assert_eq!(
+            Some("4".to_string()),
+            help.options.find("--jobs").unwrap().default
+        );
+
+        assert_eq!(2, help.subcommands.len());
+        assert_eq!("build", help.subcommand("b").unwrap().name);
+        assert_eq!(
+            "Remove the target directory and its contents",
+            help.subcommand("clean").unwrap().description
+        );
+    }
+
+    #[test]
+    fn argparse_help() {
+        let help = Help::parse(&lines(
+            "usage: tool [-h] {add,rm} ...\n\npositional arguments:\n  {add,rm}\n    add          add a file\n    rm           remove a file\n\noptions:\n  -h, --help     show this help message and exit\n  --level {debug,info}\n                 log level (default: info)\n",
+        ));
+
+        assert_eq!(
+            vec!["add", "rm"],
+            help.subcommands
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("remove a file", help.subcommand("rm").unwrap().description);
+
+        let level = help.options.find("--level").unwrap();
+        assert_eq!(vec!["debug", "info"], level.values);
+        assert_eq!(Some("info".to_string()), level.default);
+    }
+
+    #[test]
+    fn go_and_kubectl_help() {
+        let help = Help::parse(&lines(
+            "Basic Commands (Beginner):\n  create          Create a resource\n\nOptions:\n    -A, --all-namespaces=false:\n\tList across all namespaces.\n\n    --chunk-size=500:\n\tReturn large lists in chunks.\n\nUsage of prog:\n  -name string\n    \tyour name (default \"gopher\")\n  -v\tverbose output\n",
+        ));
+
+        assert_eq!("create", help.subcommands[0].name);
+
+        let all = help.options.find("--all-namespaces").unwrap();
+        assert!(!all.takes_argument());
+        assert_eq!("List across all namespaces.", all.description);
+
+        let chunk = help.options.find("--chunk-size").unwrap();
+        assert_eq!(Some("500".to_string()), chunk.default);
+
+        let name = help.options.find("-name").unwrap();
+        assert_eq!(Some("string".to_string()), name.argument);
+        assert_eq!(Some("gopher".to_string()), name.default);
+        assert_eq!(
+            "verbose output",
+            help.options.find("-v").unwrap().description
+        );
+    }
+}
@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::shell;
+
+// Overrides the history file; set but empty, it turns history off.
+pub const ENV: &str = "MAN_WITH_HISTORY";
+const FILE: &str = ".man-with.history";
+
+#[derive(Debug)]
+pub enum HistoryError {
+    // A file with lines, none of which is an entry.
+    NotHistory(PathBuf),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::NotHistory(path) => write!(
+                f,
+                "{}: not a history file, it is left as it is",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+fn one() -> u32 {
+    1
+}
+
+fn is_one(n: &u32) -> bool {
+    *n == 1
+}
+
+// One line of the history file. Lines written before the other fields
+// existed only have `command` and `argument`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub command: String,
+    pub argument: Vec<String>,
+    // Seconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    // Unknown for lines that were printed or exec'd rather than run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    // Milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    // How many runs were merged into this one when compacting.
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub uses: u32,
+}
+
+impl Entry {
+    pub fn new(command: &str, argument: Vec<String>) -> Self {
+        Entry {
+            command: command.to_string(),
+            argument,
+            time: Some(now()),
+            cwd: env::current_dir().ok(),
+            status: None,
+            duration: None,
+            uses: 1,
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status.is_some_and(|s| s != 0)
+    }
+
+    // Eight hex digits naming the run for `history rm`. Merging repeated
+    // runs into it doesn't change it, unlike its position in the file.
+    pub fn id(&self) -> String {
+        let run = Entry {
+            uses: 1,
+            ..self.clone()
+        };
+        // FNV-1a, which gives the same id from one version to the next.
+        let hash = serde_json::to_vec(&run)
+            .unwrap_or_default()
+            .iter()
+            .fold(0x811c_9dc5_u32, |hash, b| {
+                (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193)
+            });
+        format!("{:08x}", hash)
+    }
+
+    // Runs are the same for recall when they have the same line and
+    // directory, and a failure is kept apart so that it can stay hidden.
+    fn key(&self) -> (String, Option<PathBuf>, bool) {
+        (self.line(), self.cwd.clone(), self.failed())
+    }
+
+    // The command line as it would be typed in a shell.
+    pub fn line(&self) -> String {
+        shell::join(&self.command, &self.argument)
+    }
+}
+
+// How much history to keep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub max_entries: usize,
+    // Seconds.
+    pub max_age: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_entries: 10_000,
+            max_age: None,
+        }
+    }
+}
+
+// Held while the history is read or rewritten, on a file next to it since
+// rewriting replaces the history file itself.
+struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    fn new(path: &Path, operation: libc::c_int) -> Result<Self, Error> {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(name)?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Lock { _file: file })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn read(path: &Path) -> Result<Vec<Entry>, Error> {
+    if !path.exists() {
+        return Ok(Vec::default());
+    }
+
+    let _lock = Lock::new(path, libc::LOCK_SH)?;
+    read_entries(path)
+}
+
+// Lines that aren't UTF-8 or an entry, from a crash or another tool, are
+// skipped.
+fn read_entries(path: &Path) -> Result<Vec<Entry>, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    import(BufReader::new(file), Format::Json)
+}
+
+// `man-with history export` and `import` formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // The history file's own, one JSON object per line.
+    Json,
+    // One command line per line, quoted for a POSIX shell.
+    Shell,
+}
+
+pub fn export<W: Write>(entries: &[Entry], format: Format, mut output: W) -> Result<(), Error> {
+    for entry in entries {
+        match format {
+            Format::Json => serde_json::to_writer(&mut output, entry)?,
+            Format::Shell => output.write_all(entry.line().as_bytes())?,
+        }
+        output.write_all(b"\n")?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+// Lines that can't be read as an entry are skipped, and so are blank lines
+// and comments of shell scripts. Shell lines have no time or directory.
+pub fn import<R: BufRead>(input: R, format: Format) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+
+    for line in input.split(b'\n') {
+        let line = line?;
+        let line = match std::str::from_utf8(&line) {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+
+        let entry = match format {
+            Format::Json => serde_json::from_str(line).ok(),
+            Format::Shell if line.trim_start().starts_with('#') => None,
+            Format::Shell => {
+                shell::split(line)
+                    .filter(|words| !words.is_empty())
+                    .map(|mut words| {
+                        let command = words.remove(0);
+                        Entry {
+                            time: None,
+                            cwd: None,
+                            ..Entry::new(&command, words)
+                        }
+                    })
+            }
+        };
+        entries.extend(entry);
+    }
+    Ok(entries)
+}
+
+// The history file as it is before a change.
+struct Contents {
+    entries: Vec<Entry>,
+    // Lines that aren't an entry, such as a write cut short.
+    unreadable: Vec<Vec<u8>>,
+    // Whether the last line ends with a newline.
+    complete: bool,
+}
+
+// A file with lines but no entry at all is something else than a history,
+// and is refused.
+fn load(path: &Path) -> Result<Contents, Error> {
+    let text = match fs::read(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut contents = Contents {
+        entries: Vec::new(),
+        unreadable: Vec::new(),
+        complete: text.last().is_none_or(|b| *b == b'\n'),
+    };
+    for line in text.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+        match serde_json::from_slice(line) {
+            Ok(entry) => contents.entries.push(entry),
+            Err(_) => contents.unreadable.push(line.to_vec()),
+        }
+    }
+
+    if contents.entries.is_empty() && !contents.unreadable.is_empty() {
+        return Err(HistoryError::NotHistory(path.to_path_buf()).into());
+    }
+    Ok(contents)
+}
+
+// Reads, changes and writes back the history while holding the lock, so
+// that sessions finishing at the same time don't lose each other's lines.
+pub fn update<F>(path: &Path, change: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Vec<Entry>),
+{
+    let _lock = Lock::new(path, libc::LOCK_EX)?;
+    rewrite(path, load(path)?, change)
+}
+
+// Unreadable lines are dropped, after adding them to `<history>.bak`.
+fn rewrite<F>(path: &Path, mut contents: Contents, change: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Vec<Entry>),
+{
+    if !contents.unreadable.is_empty() {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".bak");
+        let mut backup = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(name)?;
+        for line in &contents.unreadable {
+            backup.write_all(line)?;
+            backup.write_all(b"\n")?;
+        }
+    }
+    change(&mut contents.entries);
+
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(name);
+    {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        let mut file = BufWriter::new(file);
+        for entry in &contents.entries {
+            serde_json::to_writer(&mut file, entry)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+    }
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+// Adds one line at the end. The history is compacted instead when the
+// entry repeats an earlier one, when it is past the limits, or when it has
+// unreadable lines.
+pub fn append(path: &Path, entry: &Entry, limits: &Limits) -> Result<(), Error> {
+    let _lock = Lock::new(path, libc::LOCK_EX)?;
+    let contents = load(path)?;
+
+    let now = now();
+    let key = entry.key();
+    let repeated = contents.entries.iter().any(|e| e.key() == key);
+    // The first line is the oldest.
+    let expired = contents
+        .entries
+        .first()
+        .and_then(|e| e.time)
+        .is_some_and(|t| {
+            limits
+                .max_age
+                .is_some_and(|max| now.saturating_sub(t) > max)
+        });
+    if repeated
+        || expired
+        || contents.entries.len() >= limits.max_entries
+        || !contents.unreadable.is_empty()
+    {
+        return rewrite(path, contents, |entries| {
+            entries.push(entry.clone());
+            compact(entries, limits, now)
+        });
+    }
+
+    let mut line = Vec::new();
+    // After a write cut short, the new entry starts a line of its own.
+    if !contents.complete {
+        line.push(b'\n');
+    }
+    serde_json::to_writer(&mut line, entry)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(&line)?;
+
+    Ok(())
+}
+
+// Merges repeated runs into the latest one, then drops what is older than
+// `max_age` and the oldest entries past `max_entries`. Entries from before
+// times were recorded are only dropped for space.
+pub fn compact(entries: &mut Vec<Entry>, limits: &Limits, now: u64) {
+    let mut kept: Vec<Entry> = Vec::with_capacity(entries.len());
+    let mut latest: HashMap<(String, Option<PathBuf>, bool), usize> = HashMap::new();
+
+    for entry in entries.drain(..).rev() {
+        let key = entry.key();
+        match latest.get(&key) {
+            Some(&i) => kept[i].uses += entry.uses,
+            None => {
+                latest.insert(key, kept.len());
+                kept.push(entry);
+            }
+        }
+    }
+    if let Some(max_age) = limits.max_age {
+        kept.retain(|e| e.time.is_none_or(|t| now.saturating_sub(t) <= max_age));
+    }
+    kept.truncate(limits.max_entries);
+    kept.reverse();
+
+    *entries = kept;
+}
+
+// Recent uses count for more, like the frecency of browsers' address bars.
+fn weight(age: u64) -> f64 {
+    match age {
+        a if a < HOUR => 4.0,
+        a if a < DAY => 2.0,
+        a if a < WEEK => 1.0,
+        _ => 0.5,
+    }
+}
+
+#[derive(Default)]
+struct Rank {
+    // Used in the current directory.
+    here: bool,
+    frecency: f64,
+    last: usize,
+}
+
+// The argument lists used with `command`, each once and the most relevant
+// last: those used in `cwd` first, then by frecency. Runs that failed are
+// left out unless `failed` is set.
+pub fn recall(
+    entries: &[Entry],
+    command: &str,
+    cwd: &Path,
+    now: u64,
+    failed: bool,
+) -> Vec<Vec<String>> {
+    let mut ranks: HashMap<&[String], Rank> = HashMap::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.command != command || (entry.failed() && !failed) {
+            continue;
+        }
+
+        let rank = ranks.entry(&entry.argument).or_default();
+        rank.here |= entry.cwd.as_deref() == Some(cwd);
+        // Merged runs are counted as recent as the latest of them.
+        rank.frecency +=
+            weight(now.saturating_sub(entry.time.unwrap_or_default())) * f64::from(entry.uses);
+        rank.last = i;
+    }
+
+    let mut ranked = ranks.into_iter().collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| {
+        a.here
+            .cmp(&b.here)
+            .then(a.frecency.partial_cmp(&b.frecency).unwrap())
+            .then(a.last.cmp(&b.last))
+    });
+
+    ranked
+        .into_iter()
+        .map(|(argument, _)| {
+            let mut argument = argument.to_vec();
+            argument.push(String::default());
+            argument
+        })
+        .collect()
+}
+
+// The nearest `.man-with.history` in `dir` or one of its parents, so that
+// creating one in a repository keeps its commands apart from the rest.
+// The walk stops below the home directory, whose `.man-with.history` is
+// the default file rather than a project's.
+pub fn project(dir: &Path) -> Option<PathBuf> {
+    let home = dirs::home_dir();
+    dir.ancestors()
+        .take_while(|d| Some(*d) != home.as_deref())
+        .map(|d| d.join(FILE))
+        .find(|p| p.is_file())
+}
+
+// `~/.man-with.history`.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|dir| dir.join(FILE))
+}
+
+// Where history is kept when no option says otherwise: `$MAN_WITH_HISTORY`,
+// then a project history, then the configured file, then the default.
+pub fn locate(dir: &Path, configured: Option<PathBuf>) -> Option<PathBuf> {
+    if let Some(path) = env::var_os(ENV) {
+        return Some(path)
+            .filter(|p| !p.is_empty())
+            .map(|p| PathBuf::from(shellexpand::tilde(&p.to_string_lossy()).as_ref()));
+    }
+
+    project(dir).or(configured).or_else(default_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn project_history() {
+        let dir = env::temp_dir().join(format!("man-with-history-{}", std::process::id()));
+        let nested = dir.join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(None, project(&nested));
+
+        fs::write(dir.join(FILE), "").unwrap();
+        assert_eq!(Some(dir.join(FILE)), project(&nested));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn frecency() {
+        let line = |command: &str, argument: &[&str], time: u64, cwd: &str, status: i32| Entry {
+            command: command.to_string(),
+            argument: argument.iter().map(ToString::to_string).collect(),
+            time: Some(time),
+            cwd: Some(PathBuf::from(cwd)),
+            status: Some(status),
+            duration: Some(10),
+            uses: 1,
+        };
+        let now = 100 * DAY;
+        let mut entries = vec![
+            serde_json::from_str(r#"{"command":"ls","argument":["-a"]}"#).unwrap(),
+            line("ls", &["-l"], now - 2 * DAY, "/src", 0),
+            line("ls", &["-l"], now - 3 * DAY, "/src", 0),
+            line("ls", &["-R"], now - 60, "/src", 0),
+            line("ls", &["-Z"], now - 60, "/src", 2),
+            line("git", &["log"], now - 60, "/src", 0),
+            line("ls", &["-d"], now - 10 * DAY, "/tmp", 0),
+        ];
+        assert_eq!(None, entries[0].time);
+
+        let arguments = |entries: &[Entry], cwd: &str, failed: bool| {
+            recall(entries, "ls", Path::new(cwd), now, failed)
+                .into_iter()
+                .map(|a| a[0].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec!["-a", "-d", "-l", "-R"],
+            arguments(&entries, "/src", false)
+        );
+        assert_eq!(
+            vec!["-a", "-l", "-R", "-d"],
+            arguments(&entries, "/tmp", false)
+        );
+        assert_eq!(
+            vec!["-a", "-d", "-l", "-R", "-Z"],
+            arguments(&entries, "/src", true)
+        );
+
+        entries.push(line("ls", &["-a"], now, "/src", 0));
+        assert_eq!("-a", arguments(&entries, "/src", false)[3]);
+
+        let limits = Limits {
+            max_entries: 5,
+            max_age: Some(5 * DAY),
+        };
+        compact(&mut entries, &limits, now);
+        let kept = entries
+            .iter()
+            .map(|e| (e.argument[0].as_str(), e.uses))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("-l", 2), ("-R", 1), ("-Z", 1), ("log", 1), ("-a", 1)],
+            kept
+        );
+    }
+
+    #[test]
+    fn storage() {
+        let dir = env::temp_dir().join(format!("man-with-storage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history");
+        let mut text = b"{\"command\":\"ls\",\"argument\":[\"-a\"]}\n{\"comm".to_vec();
+        text.extend_from_slice(b"\n\xff\xfe\n{\"command\":\"ls\",\"argument\":[\"-l\"]}\n");
+        fs::write(&path, &text).unwrap();
+        assert_eq!(2, read(&path).unwrap().len());
+
+        let limits = Limits {
+            max_entries: 2,
+            max_age: None,
+        };
+        let entry = Entry::new("ls", vec!["-R".to_string()]);
+        append(&path, &entry, &limits).unwrap();
+        let backup = dir.join("history.bak");
+        assert_eq!(&b"{\"comm\n\xff\xfe\n"[..], &fs::read(&backup).unwrap()[..]);
+
+        // A repeated run is merged right away.
+        let limits = Limits::default();
+        append(&path, &entry, &limits).unwrap();
+        let entries = read(&path).unwrap();
+        assert_eq!(vec!["-l"], entries[0].argument);
+        assert_eq!(
+            Entry {
+                uses: 2,
+                ..entry.clone()
+            },
+            entries[1]
+        );
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+        assert_eq!(entry.id(), entries[1].id());
+        assert_ne!(entries[0].id(), entries[1].id());
+
+        let other = Entry::new("ls", vec!["-a".to_string()]);
+        fs::write(&path, "{\"command\":\"ls\",\"argument\":[\"-l\"]}").unwrap();
+        append(&path, &other, &limits).unwrap();
+        assert_eq!(2, read(&path).unwrap().len());
+
+        let bashrc = b"# ~/.bashrc\nalias ll='ls -l'\n";
+        fs::write(&path, bashrc).unwrap();
+        let e = append(&path, &entry, &limits).unwrap_err();
+        assert!(e
+            .to_string()
+            .ends_with("not a history file, it is left as it is"));
+        assert_eq!(&bashrc[..], &fs::read(&path).unwrap()[..]);
+
+        let mut script = Vec::new();
+        export(&entries, Format::Shell, &mut script).unwrap();
+        assert_eq!("ls -l\nls -R\n", String::from_utf8_lossy(&script));
+        let script = b"# ~/.bash_history\n\ngit commit -m 'a b'\necho 'open\n";
+        let imported = import(&script[..], Format::Shell).unwrap();
+        assert_eq!(1, imported.len());
+        assert_eq!("git commit -m 'a b'", imported[0].line());
+        assert_eq!(None, imported[0].time);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
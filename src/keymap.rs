@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use failure::Error;
+use termion::event::Key;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Accept,
+    DeleteBackward,
+    DeleteForward,
+    Complete,
+    CompletePrevious,
+    Cancel,
+    Quit,
+    ForwardChar,
+    BackwardChar,
+    NextArgument,
+    PreviousArgument,
+    BeginningOfLine,
+    EndOfLine,
+    NextMatch,
+    PreviousMatch,
+    PreviousLine,
+    NextLine,
+    HistorySearch,
+    HistorySearchForward,
+    ToggleLineNumbers,
+    GrowPanel,
+    ShrinkPanel,
+    ShowBindings,
+}
+
+// Every action with its name in the configuration and default bindings.
+const ACTIONS: &[(Action, &str, &[&str])] = &[
+    (Action::Accept, "accept", &["Enter"]),
+    (Action::DeleteBackward, "delete-backward", &["Backspace"]),
+    (Action::DeleteForward, "delete-forward", &["Delete", "C-d"]),
+    (Action::Complete, "complete", &["Tab"]),
+    (Action::CompletePrevious, "complete-previous", &["S-Tab"]),
+    (Action::Cancel, "cancel", &["Esc"]),
+    (Action::Quit, "quit", &["C-c"]),
+    (Action::ForwardChar, "forward-char", &["C-f"]),
+    (Action::BackwardChar, "backward-char", &["C-b"]),
+    (Action::NextArgument, "next-argument", &["Right"]),
+    (Action::PreviousArgument, "previous-argument", &["Left"]),
+    (Action::BeginningOfLine, "beginning-of-line", &["C-a"]),
+    (Action::EndOfLine, "end-of-line", &["C-e"]),
+    (Action::NextMatch, "next-match", &["C-n"]),
+    (Action::PreviousMatch, "previous-match", &["C-p"]),
+    (Action::PreviousLine, "previous-line", &["Up"]),
+    (Action::NextLine, "next-line", &["Down"]),
+    (Action::HistorySearch, "history-search", &["C-r"]),
+    (
+        Action::HistorySearchForward,
+        "history-search-forward",
+        &["C-s"],
+    ),
+    (Action::ToggleLineNumbers, "toggle-line-numbers", &["F1"]),
+    (Action::GrowPanel, "grow-panel", &["F2"]),
+    (Action::ShrinkPanel, "shrink-panel", &["F3"]),
+    (Action::ShowBindings, "show-bindings", &["C-x ?"]),
+];
+
+impl Action {
+    fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, n, _)| *n == name)
+            .map(|(a, _, _)| *a)
+    }
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Key(String),
+    Action(String, String),
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::Key(keys) => write!(
+                f,
+                "invalid key `{}`, expected keys such as `a`, `C-x`, `M-x`, `Tab` or `F1` separated by spaces",
+                keys
+            ),
+            KeymapError::Action(keys, action) => {
+                write!(f, "unknown action `{}` for `{}`", action, keys)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+const NAMES: &[(&str, Key)] = &[
+    ("Enter", Key::Char('\n')),
+    ("RET", Key::Char('\n')),
+    ("Tab", Key::Char('\t')),
+    ("S-Tab", Key::BackTab),
+    ("Space", Key::Char(' ')),
+    ("SPC", Key::Char(' ')),
+    ("Esc", Key::Esc),
+    ("Backspace", Key::Backspace),
+    ("Delete", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+];
+
+// Emacs style names: `C-x` (control), `M-x` (alt), `F1`, `Tab`, ...
+fn parse_key(name: &str) -> Option<Key> {
+    if let Some((_, key)) = NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(*key);
+    }
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        chars.next().filter(|_| chars.next().is_none())
+    };
+
+    if let Some(c) = name.strip_prefix("C-").and_then(single) {
+        return Some(Key::Ctrl(c.to_ascii_lowercase()));
+    }
+    if let Some(c) = name.strip_prefix("M-").and_then(single) {
+        return Some(Key::Alt(c));
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+        return Some(Key::F(n)).filter(|_| (1..=12).contains(&n));
+    }
+    single(name).map(Key::Char)
+}
+
+fn key_name(key: Key) -> String {
+    if let Some((name, _)) = NAMES.iter().find(|(_, k)| *k == key) {
+        return name.to_string();
+    }
+    match key {
+        Key::Ctrl(c) => format!("C-{}", c),
+        Key::Alt(c) => format!("M-{}", c),
+        Key::F(n) => format!("F{}", n),
+        Key::Char(c) => c.to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+fn parse_keys(keys: &str) -> Result<Vec<Key>, KeymapError> {
+    let sequence = keys
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Option<Vec<_>>>()
+        .filter(|s| !s.is_empty());
+
+    sequence.ok_or_else(|| KeymapError::Key(keys.to_string()))
+}
+
+pub enum Lookup {
+    Action(Action),
+    // The keys so far start a longer binding.
+    Prefix,
+    None,
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = ACTIONS
+            .iter()
+            .flat_map(|(action, _, keys)| {
+                keys.iter().map(move |k| (parse_keys(k).unwrap(), *action))
+            })
+            .collect();
+
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    // The defaults with the `[keys]` table of the configuration applied on
+    // top. An action of "none" removes a binding.
+    pub fn new(keys: &BTreeMap<String, String>) -> Result<Self, Error> {
+        let mut keymap = Keymap::default();
+
+        for (sequence, action) in keys {
+            let sequence_keys = parse_keys(sequence)?;
+            keymap.bindings.retain(|(k, _)| *k != sequence_keys);
+
+            if action != "none" {
+                let action = Action::from_name(action)
+                    .ok_or_else(|| KeymapError::Action(sequence.clone(), action.clone()))?;
+                keymap.bindings.push((sequence_keys, action));
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        if let Some((_, action)) = self.bindings.iter().find(|(k, _)| k.as_slice() == keys) {
+            return Lookup::Action(*action);
+        }
+        if self.bindings.iter().any(|(k, _)| k.starts_with(keys)) {
+            return Lookup::Prefix;
+        }
+        Lookup::None
+    }
+
+    // One line per action for the bindings overlay.
+    pub fn describe(&self) -> Vec<String> {
+        ACTIONS
+            .iter()
+            .filter_map(|(action, name, _)| {
+                let keys = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, a)| a == action)
+                    .map(|(k, _)| k.iter().map(|k| key_name(*k)).collect::<Vec<_>>().join(" "))
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    None
+                } else {
+                    Some(format!("{:<20} {}", keys.join(", "), name))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sequences_and_overrides() {
+        let mut keys = BTreeMap::new();
+        keys.insert("C-x C-e".to_string(), "toggle-line-numbers".to_string());
+        keys.insert("F1".to_string(), "none".to_string());
+        let keymap = Keymap::new(&keys).unwrap();
+
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Lookup::Prefix));
+        assert!(matches!(
+            keymap.lookup(&[Key::Ctrl('x'), Key::Ctrl('e')]),
+            Lookup::Action(Action::ToggleLineNumbers)
+        ));
+        assert!(matches!(keymap.lookup(&[Key::F(1)]), Lookup::None));
+        assert!(matches!(
+            keymap.lookup(&[Key::Delete]),
+            Lookup::Action(Action::DeleteForward)
+        ));
+        assert!(keymap
+            .describe()
+            .contains(&format!("{:<20} {}", "C-x C-e", "toggle-line-numbers")));
+
+        keys.insert("C-x C-e".to_string(), "edit".to_string());
+        let e = Keymap::new(&keys).err().unwrap();
+        assert_eq!("unknown action `edit` for `C-x C-e`", e.to_string());
+    }
+}
@@ -6,9 +6,9 @@ extern crate terminal_size;
 extern crate termion;
 extern crate unicode_width;
 
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Stdout};
-use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Stdout, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 use std::path::PathBuf;
 use std::sync::{
     mpsc::{self, Receiver, Sender},
@@ -17,23 +17,35 @@ use std::sync::{
 use std::thread::{self, JoinHandle};
 
 use failure::Error;
+use termion::event::Key;
+use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 
+pub mod config;
+pub mod help;
+pub mod history;
+pub mod man;
+pub mod options;
+pub mod policy;
+pub mod shell;
+
+mod cache;
 mod event;
+mod fuzzy;
+mod keymap;
 mod ui;
 
+use self::config::Config;
 use self::event::Event;
-use self::ui::{
-    prompt::{Prompt, PromptMode},
-    viewer::ShowType,
-    Input,
-};
+use self::keymap::{Action, Keymap};
+use self::ui::{prompt::PromptMode, viewer::ShowType, Input, Prompt};
 
 pub type CommandWithArgument = (String, Vec<String>);
 
 pub struct ManWith {
     source: Arc<Mutex<Option<BufReader<File>>>>,
     prompt: Arc<Mutex<Prompt<RawTerminal<Stdout>>>>,
+    keymap: Keymap,
 }
 
 impl ManWith {
@@ -52,27 +64,54 @@ impl ManWith {
 
         ManWith {
             source: Arc::new(Mutex::new(source)),
-            prompt: prompt,
+            prompt,
+            keymap: Keymap::default(),
         }
     }
 
-    pub fn run(&self) -> Result<CommandWithArgument, Error> {
+    // Fails when the `[keys]` table names unknown keys or actions.
+    pub fn configure(&mut self, config: Config) -> Result<(), Error> {
+        self.keymap = Keymap::new(&config.keys)?;
+        self.prompt.lock().unwrap().set_config(config);
+        Ok(())
+    }
+
+    // Lets `|`, `&&` and friends separate commands, for lines run by `$SHELL`.
+    pub fn use_shell(&self, shell: bool) {
+        self.prompt.lock().unwrap().set_shell(shell);
+    }
+
+    // The arguments come with whether they were quoted.
+    pub fn preload(&self, arguments: Vec<(String, bool)>) {
+        self.prompt.lock().unwrap().preload(arguments);
+    }
+
+    // The line accepted by `run`, for `$SHELL -c`.
+    pub fn shell_line(&self) -> String {
+        self.prompt.lock().unwrap().shell_line()
+    }
+
+    // Returns `None` when the user quit with C-c instead of accepting the line.
+    pub fn run(&self) -> Result<Option<CommandWithArgument>, Error> {
+        let (tx, rx) = mpsc::channel();
         {
             let mut f = self.prompt.lock().unwrap();
 
             f.read_history();
+            f.set_loader(tx.clone());
+            f.load();
             f.show()?;
             f.flush()?;
         }
 
-        let (tx, rx) = mpsc::channel();
         let th = {
             self.input_handler(tx.clone());
             self.event_handler(tx.clone(), rx)
         };
 
+        let keymap = self.keymap.clone();
         thread::spawn(move || {
-            let _ = Input::reader(tx.clone());
+            let _ = Input::reader(tx.clone(), keymap);
         });
 
         let _ = th.join();
@@ -82,10 +121,9 @@ impl ManWith {
         ui::cursor::horizon(&mut f.stdout, 1u64);
         ui::cursor::clear_line(&mut f.stdout);
 
-        f.write_history();
         f.flush()?;
 
-        Ok(f.full_command())
+        Ok(f.accepted_command())
     }
 
     pub fn input_handler(&self, tx: Sender<Event>) -> JoinHandle<()> {
@@ -98,10 +136,10 @@ impl ManWith {
                 let mut buf = vec![];
                 match b.read_until(b'\n', &mut buf) {
                     Ok(n) if n != 0 => {
-                        if buf.ends_with(&[b'\n']) || buf.ends_with(&[b'\0']) {
+                        if buf.ends_with(b"\n") || buf.ends_with(b"\0") {
                             buf.pop();
                         }
-                        let l = String::from_utf8(buf).unwrap_or(String::new());
+                        let l = String::from_utf8(buf).unwrap_or_default();
                         let _ = tx.send(Event::ReadLine(l));
                     }
                     _ => {}
@@ -112,71 +150,74 @@ impl ManWith {
 
     pub fn event_handler(&self, _tx: Sender<Event>, rx: Receiver<Event>) -> JoinHandle<()> {
         let prompt = self.prompt.clone();
+        let bindings = self.keymap.describe();
 
         thread::spawn(move || {
             loop {
                 match rx.recv() {
-                    Ok(Event::Quit) => {
+                    Ok(Event::Action(Action::Quit)) => {
                         // Quit message.
-                        let _ = prompt.lock().and_then(|f| {
+                        let _ = prompt.lock().map(|mut f| {
                             f.quit();
-                            Ok(())
                         });
                         break;
                     }
                     Ok(Event::ReadLine(line)) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                        let _ = prompt.lock().map(|mut f| {
                             f.insert_line(line);
-                            Ok(())
                         });
                     }
+                    Ok(Event::Source(generation, lines)) => {
+                        let _ = prompt.lock().map(|mut f| f.receive(generation, lines));
+                    }
+                    Ok(Event::Loaded(generation, help)) => {
+                        let _ = prompt.lock().map(|mut f| f.loaded(generation, help));
+                    }
                     Ok(Event::Key(ch)) => {
-                        let _ = prompt.lock().and_then(|mut f| {
-                            match f.get_mode() {
-                                ui::prompt::PromptMode::Choose => {}
-                                ui::prompt::PromptMode::Prompt | ui::prompt::PromptMode::File => {
-                                    match ch {
-                                        ' ' => f.append(),
-                                        _ => f.insert(ch),
-                                    }
-                                }
-                                _ => {}
+                        let _ = prompt.lock().map(|mut f| match f.get_mode() {
+                            ui::prompt::PromptMode::Choose => {}
+                            ui::prompt::PromptMode::Prompt | ui::prompt::PromptMode::File => {
+                                f.key(ch)
                             }
-
-                            Ok(())
                         });
                     }
-                    Ok(Event::Candidate(candidate)) if candidate == PromptMode::File => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Candidate(PromptMode::File)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.set_mode(ui::prompt::PromptMode::File);
-                            Ok(())
                         });
                     }
-                    Ok(Event::Candidate(candidate)) if candidate == PromptMode::Choose => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Candidate(PromptMode::Choose)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.set_mode(ui::prompt::PromptMode::Choose);
-                            Ok(())
                         });
                     }
-                    Ok(Event::Backspace) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::DeleteBackward)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.backspace();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Delete) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::DeleteForward)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.delete();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Tab) => {
-                        let _ = prompt.lock().and_then(|mut f| {
-                            f.completion();
-                            Ok(())
+                    Ok(Event::Action(Action::Complete)) => {
+                        let _ = prompt.lock().map(|mut f| {
+                            f.tab();
                         });
                     }
-                    Ok(Event::Enter) => {
+                    Ok(Event::Action(Action::CompletePrevious)) => {
+                        let _ = prompt.lock().map(|mut f| {
+                            f.back_tab();
+                        });
+                    }
+                    Ok(Event::Action(Action::Cancel)) => {
+                        let quitted = prompt.lock().map(|mut f| f.escape()).unwrap_or(true);
+                        if quitted {
+                            break;
+                        }
+                    }
+                    Ok(Event::Action(Action::Accept)) => {
                         let mut f = prompt.lock().unwrap();
                         match f.get_mode() {
                             ui::prompt::PromptMode::Choose => {
@@ -191,7 +232,11 @@ impl ManWith {
                                 }
                             }
                             ui::prompt::PromptMode::Prompt => {
-                                if f.cursor > 0 {
+                                if f.is_searching() {
+                                    f.accept_search();
+                                } else if f.is_menu_open() {
+                                    f.accept_menu();
+                                } else if f.cursor > 0 {
                                     if !f.argument.is_empty() {
                                         f.append();
                                     }
@@ -205,112 +250,140 @@ impl ManWith {
                                 f.clear_cache();
                                 f.append();
                             }
-                            _ => f.append(),
                         }
                     }
-                    Ok(Event::Up) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::PreviousLine)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.up();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Down) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::NextLine)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.down();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Left) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::PreviousArgument)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.select_back();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Right) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::NextArgument)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.select_forward();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Next) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::NextMatch)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.next();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Prev) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::PreviousMatch)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.prev();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Forward) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::ForwardChar)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.cursor_forward();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Back) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::BackwardChar)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.cursor_back();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Fn1) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::ToggleLineNumbers)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.viewer.toggle_show_type(ShowType::LineNumber);
-                            Ok(())
                         });
                     }
-                    Ok(Event::Fn2) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::GrowPanel)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.incr_size();
-                            Ok(())
                         });
                     }
-                    Ok(Event::Fn3) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::ShrinkPanel)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.decr_size();
-                            Ok(())
                         });
                     }
-                    Ok(Event::History) => {
-                        let _ = prompt.lock().and_then(|mut f| {
-                            f.set_mode(ui::prompt::PromptMode::History);
-                            f.history_back();
-
-                            Ok(())
+                    Ok(Event::Action(Action::HistorySearch)) => {
+                        let _ = prompt.lock().map(|mut f| {
+                            f.search_backward();
                         });
                     }
-                    Ok(Event::MoveTo(0)) => {
-                        let _ = prompt.lock().and_then(|mut f| {
+                    Ok(Event::Action(Action::HistorySearchForward)) => {
+                        let _ = prompt.lock().map(|mut f| {
+                            f.search_forward();
+                        });
+                    }
+                    Ok(Event::Action(Action::BeginningOfLine)) => {
+                        let _ = prompt.lock().map(|mut f| {
                             f.beginning_of_line();
-                            Ok(())
                         });
                     }
-                    Ok(Event::MoveTo(n)) => {
-                        if n < 0 {
-                            let _ = prompt.lock().and_then(|mut f| {
-                                f.end_of_line();
-                                Ok(())
-                            });
-                        }
+                    Ok(Event::Action(Action::EndOfLine)) => {
+                        let _ = prompt.lock().map(|mut f| {
+                            f.end_of_line();
+                        });
+                    }
+                    Ok(Event::Action(Action::ShowBindings)) => {
+                        let _ = prompt.lock().map(|mut f| {
+                            f.toggle_bindings(&bindings);
+                        });
                     }
                     _ => break,
                 };
 
-                let _ = prompt.lock().and_then(|mut f| {
+                let _ = prompt.lock().map(|mut f| {
                     f.show().and_then(|_| f.flush()).unwrap();
-                    Ok(())
                 });
             }
         })
     }
 }
 
+// Shows `line` on the terminal and waits for y or n.
+pub fn confirm(line: &str) -> Result<bool, Error> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let mut out = tty.try_clone()?.into_raw_mode()?;
+
+    write!(
+        out,
+        "{bold}This command needs to be confirmed:{reset}\r\n\r\n    {line}\r\n\r\nRun it? [y/N] ",
+        bold = termion::style::Bold,
+        reset = termion::style::Reset,
+        line = line
+    )?;
+    out.flush()?;
+
+    let answer = tty.keys().next().transpose()?;
+    write!(out, "\r\n")?;
+
+    Ok(matches!(
+        answer,
+        Some(Key::Char('y')) | Some(Key::Char('Y'))
+    ))
+}
+
+// Moves stdout to the terminal so that the UI can be drawn while the
+// original stdout, returned here, is kept for printing the command line.
+pub fn redirect_stdout() -> Result<File, Error> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+
+    unsafe {
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 || libc::dup2(tty.as_raw_fd(), libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(File::from_raw_fd(stdout))
+    }
+}
+
 fn source() -> Option<BufReader<File>> {
     unsafe {
-        let isatty = libc::isatty(libc::STDIN_FILENO as i32) != 0;
+        let isatty = libc::isatty(libc::STDIN_FILENO) != 0;
         if !isatty {
             let stdin = File::from_raw_fd(libc::dup(libc::STDIN_FILENO));
             let file = File::open("/dev/tty").unwrap();
@@ -2,30 +2,53 @@
 extern crate clap;
 extern crate env_logger;
 extern crate failure;
+extern crate libc;
 extern crate man_with;
 extern crate terminal_size;
 extern crate termion;
 extern crate unicode_width;
 
 use std::env;
-use std::path::PathBuf;
-use std::process::Command;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::Instant;
 
-use clap::{App, Arg};
-use failure::Error;
-use man_with::ManWith;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::{err_msg, Error};
+use glob::Pattern;
+use man_with::config::Config;
+use man_with::history::{self, Format, Limits};
+use man_with::policy::{Policy, Verdict};
+use man_with::{shell, CommandWithArgument, ManWith};
 
 fn main() -> Result<(), Error> {
     env_logger::init();
 
     let matches = App::new("Man with a command")
         .version(crate_version!())
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::TrailingVarArg)
         .arg(
             Arg::with_name("COMMAND")
-                .required(true)
-                .help("Sets the man command.")
+                .required_unless("LINE")
+                .help("Sets the man command, optionally followed by arguments to start with.")
+                .value_name("COMMAND [ARGUMENTS]...")
+                .multiple(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("LINE")
+                .long("line")
+                .value_name("LINE")
+                .conflicts_with("COMMAND")
+                .help("Starts from a command line as typed in a shell, instead of COMMAND [ARGUMENTS]...")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("SIZE")
                 .long("size")
@@ -40,29 +63,437 @@ fn main() -> Result<(), Error> {
                 .short("p")
                 .help("Using the --help instead of man command"),
         )
+        .arg(
+            Arg::with_name("HISTORY")
+                .long("history")
+                .value_name("PATH")
+                .help("Keeps the history in PATH instead of ~/.man-with.history or a project's .man-with.history.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("NO_HISTORY")
+                .long("no-history")
+                .conflicts_with("HISTORY")
+                .help("Neither reads nor writes the history."),
+        )
+        .arg(
+            Arg::with_name("USE_SHELL")
+                .long("shell")
+                .help("Runs the command line with $SHELL, so that pipes, redirections, globs and variables work."),
+        )
+        .arg(
+            Arg::with_name("EXEC")
+                .long("exec")
+                .help("Replaces man-with with the command instead of running it as a child."),
+        )
+        .arg(
+            Arg::with_name("POLICY")
+                .long("policy")
+                .value_name("FILE")
+                .help("Reads the commands that need confirmation or are allowed from FILE.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("PRINT")
+                .long("print")
+                .help("Prints the command line to stdout instead of executing it."),
+        )
+        .arg(
+            Arg::with_name("OUTPUT_FD")
+                .long("output-fd")
+                .value_name("FD")
+                .help("Prints the command line to the file descriptor FD. Implies --print.")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Prints key bindings for a shell, e.g. `eval \"$(man-with init bash)\"`.")
+                .arg(
+                    Arg::with_name("SHELL")
+                        .required(true)
+                        .possible_values(shell::SHELLS),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Lists, searches and edits the history, oldest entries first. Each entry is listed with an id for rm.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(
+                    Arg::with_name("FILE")
+                        .long("file")
+                        .short("f")
+                        .value_name("PATH")
+                        .help("Uses the history in PATH instead of the one man-with would use here.")
+                        .takes_value(true),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists the entries, or only those of COMMAND.")
+                        .arg(Arg::with_name("COMMAND")),
+                )
+                .subcommand(
+                    SubCommand::with_name("search")
+                        .about("Lists the entries whose command line contains TEXT.")
+                        .arg(Arg::with_name("TEXT").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("rm")
+                        .about("Removes the entries with ENTRY as their id or command line, as listed.")
+                        .arg(
+                            Arg::with_name("GLOB")
+                                .long("glob")
+                                .help("Takes each ENTRY as a glob pattern matching command lines."),
+                        )
+                        .arg(
+                            Arg::with_name("ENTRY")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Merges repeated entries and drops those past the configured limits.")
+                        .arg(
+                            Arg::with_name("DAYS")
+                                .long("older-than")
+                                .value_name("DAYS")
+                                .help("Drops entries older than DAYS instead of history_max_age.")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Prints the entries, or only those of COMMAND.")
+                        .arg(format_arg())
+                        .arg(Arg::with_name("COMMAND")),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Adds the entries of FILE, or of stdin with -.")
+                        .arg(format_arg())
+                        .arg(Arg::with_name("FILE").required(true)),
+                ),
+        )
         .get_matches();
 
-    let command = matches.value_of("COMMAND").unwrap();
-    let size = value_t!(matches, "SIZE", usize).unwrap_or(10);
+    if let Some(init) = matches.subcommand_matches("init") {
+        let script = init.value_of("SHELL").and_then(shell::init).unwrap();
+        print!("{}", script);
+        return Ok(());
+    }
+
+    let dir = env::current_dir()?;
+    let mut config = Config::load(&dir).unwrap_or_else(|e| fail(e));
+
+    if let Some(matches) = matches.subcommand_matches("history") {
+        let path = match matches.value_of("FILE") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => history::locate(&dir, config.history.clone()),
+        };
+        let path = path.unwrap_or_else(|| fail(err_msg("the history is turned off")));
+        if let Err(e) = history_command(matches, &path, config.history_limits()) {
+            eprintln!("man-with: {}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Anything after the command is taken as its first arguments. Words of
+    // --line keep their quoting for --shell.
+    let mut arguments = match matches.value_of("LINE") {
+        Some(line) => shell::split_quoted(line)
+            .filter(|words| !words.is_empty())
+            .unwrap_or_else(|| fail(err_msg(format!("--line: not a command line: {}", line)))),
+        None => matches
+            .values_of("COMMAND")
+            .unwrap()
+            .map(|a| (a.to_string(), false))
+            .collect(),
+    };
+    let (command, _) = arguments.remove(0);
     let help = matches.is_present("USE_HELP");
-    let history = value_t!(matches, "HISTORY", PathBuf)
-        .ok()
-        .or(dirs::home_dir().map(|dir| dir.join(PathBuf::from(".man-with.history"))));
+    let use_shell = matches.is_present("USE_SHELL");
+
+    // Flags win over the configuration files.
+    if let Ok(size) = value_t!(matches, "SIZE", usize) {
+        if size == 0 {
+            fail(err_msg("--size must be at least 1"));
+        }
+        config.size = Some(size);
+    }
+    config.history = if matches.is_present("NO_HISTORY") {
+        None
+    } else if let Ok(history) = value_t!(matches, "HISTORY", PathBuf) {
+        Some(history)
+    } else {
+        history::locate(&dir, config.history)
+    };
+
+    let policy =
+        Policy::load(matches.value_of("POLICY").map(PathBuf::from)).unwrap_or_else(|e| fail(e));
 
-    let result = run(command, size, help, history)?;
+    let output = if matches.is_present("OUTPUT_FD") {
+        let fd = value_t!(matches, "OUTPUT_FD", i32).unwrap_or_else(|e| e.exit());
+        let output = output_fd(fd).unwrap_or_else(|e| fail(e));
+        man_with::redirect_stdout()?;
+        Some(output)
+    } else if matches.is_present("PRINT") {
+        Some(man_with::redirect_stdout()?)
+    } else {
+        None
+    };
 
-    Command::new(result.0).args(result.1).spawn()?.wait()?;
+    let history_path = config.history.clone();
+    let limits = config.history_limits();
+    let (result, shell_line) = match run(&command, arguments, help, use_shell, config)? {
+        Some(result) => result,
+        // Quit with C-c, like a shell reports an interrupted line.
+        None => process::exit(128 + libc::SIGINT),
+    };
+
+    let line = if use_shell {
+        shell_line
+    } else {
+        shell::join(&result.0, &result.1)
+    };
+
+    let mut entry = history::Entry::new(&result.0, result.1.clone());
+
+    // Printing runs nothing, the policy is for what gets executed.
+    if let Some(mut output) = output {
+        record(&history_path, &entry, &limits);
+        writeln!(output, "{}", line)?;
+        return Ok(());
+    }
+
+    // With --shell, what is checked is the line the shell will run.
+    let verdict = if use_shell {
+        policy.check_line(&line)?
+    } else {
+        policy.check(&result.0, &result.1)?
+    };
+    match verdict {
+        Verdict::Run => {}
+        Verdict::Confirm => {
+            if !man_with::confirm(&line)? {
+                process::exit(1);
+            }
+        }
+        Verdict::Refuse => {
+            eprintln!("man-with: not allowed by the policy: {}", line);
+            process::exit(126);
+        }
+    }
+
+    let mut command = if use_shell {
+        let sh = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let mut command = Command::new(sh);
+        command.arg("-c").arg(line);
+        command
+    } else {
+        let mut command = Command::new(result.0);
+        command.args(result.1);
+        command
+    };
+
+    if matches.is_present("EXEC") {
+        record(&history_path, &entry, &limits);
+        return Err(command.exec().into());
+    }
+
+    let started = Instant::now();
+    // 127 like a shell, for a command that couldn't be started.
+    let status = execute(command).unwrap_or_else(|e| {
+        eprintln!("man-with: {}: {}", entry.command, e);
+        127
+    });
+    entry.status = Some(status);
+    entry.duration = Some(started.elapsed().as_millis() as u64);
+    record(&history_path, &entry, &limits);
+
+    process::exit(status)
+}
+
+// Lines without arguments are not worth recalling.
+fn record(path: &Option<PathBuf>, entry: &history::Entry, limits: &history::Limits) {
+    if let (Some(path), false) = (path, entry.argument.is_empty()) {
+        if let Err(e) = history::append(path, entry, limits) {
+            eprintln!("man-with: {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("FORMAT")
+        .long("format")
+        .value_name("FORMAT")
+        .help("JSON lines like the history file, or a shell script of command lines.")
+        .possible_values(&["json", "sh"])
+        .default_value("json")
+}
+
+fn history_command(matches: &ArgMatches, path: &Path, mut limits: Limits) -> Result<(), Error> {
+    let format = |matches: &ArgMatches| match matches.value_of("FORMAT") {
+        Some("sh") => Format::Shell,
+        _ => Format::Json,
+    };
+
+    match matches.subcommand() {
+        ("list", Some(matches)) => {
+            let command = matches.value_of("COMMAND");
+            list(&history::read(path)?, |e| {
+                command.is_none_or(|c| c == e.command)
+            });
+        }
+        ("search", Some(matches)) => {
+            let text = matches.value_of("TEXT").unwrap();
+            list(&history::read(path)?, |e| e.line().contains(text));
+        }
+        ("rm", Some(matches)) => {
+            // Ids rather than positions, which change when the history is
+            // compacted between listing and removing.
+            let wanted = matches.values_of("ENTRY").unwrap().collect::<Vec<_>>();
+            let patterns = if matches.is_present("GLOB") {
+                wanted
+                    .iter()
+                    .map(|p| Pattern::new(p).map_err(|e| (p, e)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap_or_else(|(p, e)| {
+                        fail(err_msg(format!("invalid pattern `{}`: {}", p, e.msg)))
+                    })
+            } else {
+                Vec::new()
+            };
+
+            let mut removed = 0;
+            history::update(path, |entries| {
+                entries.retain(|e| {
+                    let line = e.line();
+                    let remove = if patterns.is_empty() {
+                        let id = e.id();
+                        wanted.iter().any(|w| *w == id || *w == line)
+                    } else {
+                        patterns.iter().any(|p| p.matches(&line))
+                    };
+                    removed += remove as usize;
+                    !remove
+                });
+            })?;
+            eprintln!("man-with: removed {} entries", removed);
+        }
+        ("prune", Some(matches)) => {
+            if matches.is_present("DAYS") {
+                let days = value_t!(matches, "DAYS", u64).unwrap_or_else(|e| e.exit());
+                limits.max_age = Some(days * 24 * 60 * 60);
+            }
+            history::update(path, |entries| {
+                history::compact(entries, &limits, history::now())
+            })?;
+        }
+        ("export", Some(matches)) => {
+            let command = matches.value_of("COMMAND");
+            let entries = history::read(path)?
+                .into_iter()
+                .filter(|e| command.is_none_or(|c| c == e.command))
+                .collect::<Vec<_>>();
+            history::export(&entries, format(matches), io::stdout().lock())?;
+        }
+        ("import", Some(matches)) => {
+            let imported = match matches.value_of("FILE").unwrap() {
+                "-" => history::import(io::stdin().lock(), format(matches))?,
+                file => history::import(BufReader::new(File::open(file)?), format(matches))?,
+            };
+            let count = imported.len();
+            history::update(path, |entries| {
+                entries.extend(imported);
+                history::compact(entries, &limits, history::now())
+            })?;
+            eprintln!("man-with: imported {} entries", count);
+        }
+        _ => {}
+    }
 
     Ok(())
 }
 
-// When dropping raw mode stdout, return to original stdout.
+// The id `rm` expects, how long ago, the exit status and the line.
+fn list<F: Fn(&history::Entry) -> bool>(entries: &[history::Entry], filter: F) {
+    let now = history::now();
+
+    for entry in entries.iter().filter(|e| filter(e)) {
+        let age = match entry.time.map(|t| now.saturating_sub(t)) {
+            Some(s) if s < 60 * 60 => format!("{}m", s / 60),
+            Some(s) if s < 24 * 60 * 60 => format!("{}h", s / 60 / 60),
+            Some(s) => format!("{}d", s / 24 / 60 / 60),
+            None => "-".to_string(),
+        };
+        let status = entry
+            .status
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| "-".to_string());
+
+        println!(
+            "{}  {:>4}  {:>3}  {}",
+            entry.id(),
+            age,
+            status,
+            entry.line()
+        );
+    }
+}
+
+// The descriptor given to --output-fd, which must be open and not one of
+// the standard streams the interface itself uses.
+fn output_fd(fd: i32) -> Result<File, Error> {
+    if (0..=2).contains(&fd) {
+        return Err(err_msg(format!("--output-fd {} is a standard stream", fd)));
+    }
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        let e = std::io::Error::last_os_error();
+        return Err(err_msg(format!("--output-fd {}: {}", fd, e)));
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// Reports a mistake in the configuration without a backtrace.
+fn fail(e: Error) -> ! {
+    eprintln!("man-with: {}", e);
+    process::exit(2)
+}
+
+// Runs `command` like a shell runs a foreground job: C-c and C-\ are left
+// to the child, and its status becomes ours, 128 + N if killed by signal N.
+fn execute(mut command: Command) -> Result<i32, Error> {
+    unsafe {
+        command.pre_exec(|| {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGQUIT, libc::SIG_DFL);
+            Ok(())
+        });
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+
+    let status = command.status()?;
+    Ok(status
+        .code()
+        .or_else(|| status.signal().map(|n| 128 + n))
+        .unwrap_or(1))
+}
+
+// When dropping raw mode stdout, return to original stdout. The accepted
+// command comes with its line for `$SHELL -c`.
 fn run(
     command: &str,
-    size: usize,
+    arguments: Vec<(String, bool)>,
     help: bool,
-    path: Option<PathBuf>,
-) -> Result<(String, Vec<String>), Error> {
-    let app = ManWith::new(command, size, help, path);
-    app.run()
+    use_shell: bool,
+    config: Config,
+) -> Result<Option<(CommandWithArgument, String)>, Error> {
+    let size = config.size.unwrap_or(10);
+    let mut app = ManWith::new(command, size, help, config.history.clone());
+    app.configure(config)?;
+    app.use_shell(use_shell);
+    app.preload(arguments);
+    Ok(app.run()?.map(|result| (result, app.shell_line())))
 }
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use failure::Error;
+use flate2::read::GzDecoder;
+
+pub mod overstrike;
+pub mod roff;
+
+pub use self::roff::{Document, Font, Line, LineKind, Span};
+
+const SECTIONS: &[&str] = &["1", "n", "l", "8", "3", "0", "2", "5", "4", "9", "6", "7"];
+const MAX_INCLUDE: usize = 8;
+
+#[derive(Debug)]
+pub enum ManError {
+    NotFound(String),
+    Compression(String),
+}
+
+impl fmt::Display for ManError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManError::NotFound(name) => write!(f, "No manual entry for {}", name),
+            ManError::Compression(ext) => write!(f, "Unsupported compression: {}", ext),
+        }
+    }
+}
+
+impl std::error::Error for ManError {}
+
+pub fn render(name: &str, width: usize) -> Result<Document, Error> {
+    let path = find(name).ok_or_else(|| ManError::NotFound(name.to_string()))?;
+    let source = read(&path)?;
+
+    Ok(roff::render(&source, width))
+}
+
+pub fn find(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+
+    let dirs = manpath();
+    for section in SECTIONS {
+        for dir in &dirs {
+            if let Some(path) = find_in(&dir.join(format!("man{}", section)), name, section) {
+                return Some(path);
+            }
+        }
+    }
+
+    // Fall back to asking man(1) itself, which knows about locale and
+    // configuration specific directories.
+    Command::new("man")
+        .arg("-w")
+        .arg(name)
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .next()
+                .map(PathBuf::from)
+        })
+        .filter(|path| path.is_file())
+}
+
+pub fn read(path: &Path) -> Result<String, Error> {
+    read_source(path, 0)
+}
+
+fn read_source(path: &Path, depth: usize) -> Result<String, Error> {
+    let mut bytes = Vec::new();
+    let file = File::open(path)?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") | Some("z") => {
+            GzDecoder::new(file).read_to_end(&mut bytes)?;
+        }
+        Some(ext @ "bz2") | Some(ext @ "xz") | Some(ext @ "lzma") | Some(ext @ "zst")
+        | Some(ext @ "Z") => {
+            return Err(ManError::Compression(ext.to_string()).into());
+        }
+        _ => {
+            let mut file = file;
+            file.read_to_end(&mut bytes)?;
+        }
+    }
+
+    let source = String::from_utf8_lossy(&bytes).to_string();
+    if depth >= MAX_INCLUDE {
+        return Ok(source);
+    }
+
+    // Resolve `.so` requests relative to the root of the man hierarchy.
+    let root = path.parent().and_then(Path::parent);
+    let mut result = String::with_capacity(source.len());
+    for line in source.lines() {
+        let include = line
+            .strip_prefix(".so ")
+            .map(str::trim)
+            .and_then(|target| root.and_then(|r| resolve(r, target)));
+
+        match include {
+            Some(target) => result.push_str(&read_source(&target, depth + 1)?),
+            None => result.push_str(line),
+        }
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let base = root.join(target);
+    if base.is_file() {
+        return Some(base);
+    }
+
+    ["gz", "bz2", "xz", "Z"]
+        .iter()
+        .map(|ext| PathBuf::from(format!("{}.{}", base.display(), ext)))
+        .find(|p| p.is_file())
+}
+
+fn find_in(dir: &Path, name: &str, section: &str) -> Option<PathBuf> {
+    let prefix = format!("{}.{}", name, section);
+    let mut found = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|f| f.to_str())
+                .map(|f| f.starts_with(&prefix))
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+
+    // Prefer `ls.1.gz` over `ls.1ssl.gz` and friends.
+    found.sort_by_key(|p| p.to_string_lossy().len());
+    found.into_iter().next()
+}
+
+fn manpath() -> Vec<PathBuf> {
+    let defaults = default_manpath();
+
+    match std::env::var("MANPATH") {
+        Ok(ref value) if !value.is_empty() => {
+            let mut dirs = Vec::new();
+            for part in value.split(':') {
+                if part.is_empty() {
+                    dirs.extend(defaults.iter().cloned());
+                } else {
+                    dirs.push(PathBuf::from(part));
+                }
+            }
+            dirs
+        }
+        _ => defaults,
+    }
+}
+
+fn default_manpath() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(path) = std::env::var_os("PATH") {
+        for bin in std::env::split_paths(&path) {
+            if let Some(prefix) = bin.parent() {
+                dirs.push(prefix.join("share/man"));
+                dirs.push(prefix.join("man"));
+            }
+        }
+    }
+    for dir in &[
+        "/usr/share/man",
+        "/usr/local/share/man",
+        "/usr/local/man",
+        "/opt/homebrew/share/man",
+        "/usr/X11R6/man",
+        "/usr/man",
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+
+    let mut seen = Vec::new();
+    dirs.retain(|d| {
+        if d.is_dir() && !seen.contains(d) {
+            seen.push(d.clone());
+            true
+        } else {
+            false
+        }
+    });
+    dirs
+}
@@ -0,0 +1,59 @@
+use super::roff::{Font, Line, LineKind};
+
+// Decodes nroff's backspace overstrike, `c\bc` for bold and `_\bc` for
+// underline, as found in `man foo | man-with` or some `--help` outputs.
+pub fn decode(line: &str) -> Line {
+    let mut cells: Vec<(char, Font)> = Vec::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\u{8}' {
+            cells.push((c, Font::Regular));
+            continue;
+        }
+
+        let (prev, next) = match (cells.pop(), chars.next()) {
+            (Some((prev, _)), Some(next)) => (prev, next),
+            (prev, _) => {
+                cells.extend(prev);
+                continue;
+            }
+        };
+        let font = if prev == '_' && next != '_' {
+            Font::Italic
+        } else {
+            Font::Bold
+        };
+        cells.push((next, font));
+    }
+
+    Line::new(cells, LineKind::Text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::man::Span;
+
+    #[test]
+    fn bold_and_underline() {
+        let line = decode("-\u{8}--\u{8}-a\u{8}al\u{8}ll\u{8}l _\u{8}F_\u{8}I_\u{8}L_\u{8}E");
+
+        assert_eq!("--all FILE", line.text);
+        assert_eq!(
+            vec![
+                Span {
+                    start: 0,
+                    end: 5,
+                    font: Font::Bold
+                },
+                Span {
+                    start: 6,
+                    end: 10,
+                    font: Font::Italic
+                },
+            ],
+            line.spans
+        );
+    }
+}
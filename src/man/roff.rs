@@ -0,0 +1,2334 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde_derive::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthChar;
+
+const NBSP: char = '\u{a0}';
+const INDENT: usize = 7;
+const SUBINDENT: usize = 3;
+const MAX_EXPANSION: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Font {
+    Regular,
+    Bold,
+    Italic,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LineKind {
+    Title,
+    Heading,
+    SubHeading,
+    Tag,
+    Text,
+    Blank,
+}
+
+// A run of `text` (in bytes) set in a font other than the regular one.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub font: Font,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Line {
+    pub text: String,
+    pub kind: LineKind,
+    pub spans: Vec<Span>,
+}
+
+impl Line {
+    pub fn new<I>(cells: I, kind: LineKind) -> Self
+    where
+        I: IntoIterator<Item = (char, Font)>,
+    {
+        let mut text = String::new();
+        let mut spans: Vec<Span> = Vec::new();
+
+        for (c, font) in cells {
+            let start = text.len();
+            text.push(c);
+
+            // Underlining the blanks between italic words looks wrong.
+            if font == Font::Regular || (font == Font::Italic && c == ' ') {
+                continue;
+            }
+            match spans.last_mut() {
+                Some(span) if span.font == font && span.end == start => span.end = text.len(),
+                _ => spans.push(Span {
+                    start,
+                    end: text.len(),
+                    font,
+                }),
+            }
+        }
+
+        let len = text.trim_end().len();
+        text.truncate(len);
+        spans.retain_mut(|span| {
+            span.end = span.end.min(len);
+            span.start < span.end
+        });
+
+        Line { text, kind, spans }
+    }
+
+    fn plain(text: String, kind: LineKind) -> Self {
+        Line {
+            text,
+            kind,
+            spans: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    pub title: Option<String>,
+    pub section: Option<String>,
+    pub lines: Vec<Line>,
+}
+
+impl Document {
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|l| l.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub fn render(source: &str, width: usize) -> Document {
+    let mut r = Renderer::new(width);
+    r.input = join_continuations(source);
+
+    while let Some(line) = r.input.pop_front() {
+        r.process_line(&line);
+    }
+    r.finish()
+}
+
+type Cell = (char, Font);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pending {
+    None,
+    Tag,
+    Heading,
+    ItemHead,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ListKind {
+    Tag,
+    Hang,
+    Ohang,
+    Inset,
+    Bullet,
+    Dash,
+    Enum,
+    Item,
+    Column,
+    Display,
+}
+
+#[derive(Clone)]
+struct Block {
+    kind: ListKind,
+    width: usize,
+    count: usize,
+    compact: bool,
+    columns: Vec<usize>,
+    indent: usize,
+    hang: usize,
+    fill: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TableStage {
+    Options,
+    Format,
+    Data,
+}
+
+struct Table {
+    stage: TableStage,
+    tab: char,
+    rows: Vec<Vec<String>>,
+    block: Option<String>,
+}
+
+struct Renderer {
+    width: usize,
+    input: VecDeque<String>,
+    lines: Vec<Line>,
+    cells: Vec<Cell>,
+    tag: Option<Vec<Cell>>,
+    kind: LineKind,
+    font: Font,
+    prev_font: Font,
+    line_font: Option<Font>,
+    fill: bool,
+    indent: usize,
+    hang: usize,
+    prevailing: usize,
+    hanging: bool,
+    shift: isize,
+    pd: bool,
+    stack: Vec<(usize, usize, usize)>,
+    nospace: bool,
+    cont: bool,
+    pending: Pending,
+    strings: HashMap<String, String>,
+    macros: HashMap<String, Vec<String>>,
+    conds: Vec<bool>,
+    expansions: usize,
+    title: Option<String>,
+    section: Option<String>,
+    heading: String,
+    table: Option<Table>,
+    // mdoc
+    mdoc: bool,
+    name: Option<String>,
+    blocks: Vec<Block>,
+    glue: bool,
+    spacing: bool,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        let mut strings = HashMap::new();
+        for (k, v) in &[
+            ("R", "®"),
+            ("Tm", "™"),
+            ("lq", "“"),
+            ("rq", "”"),
+            ("Aq", "'"),
+            ("Lq", "“"),
+            ("Rq", "”"),
+            ("Gt", ">"),
+            ("Lt", "<"),
+            ("Ge", "≥"),
+            ("Le", "≤"),
+            ("Am", "&"),
+            ("Ba", "|"),
+            ("Ne", "≠"),
+            ("Pm", "±"),
+            ("If", "∞"),
+            ("Na", "NaN"),
+            ("q", "\""),
+        ] {
+            strings.insert(k.to_string(), v.to_string());
+        }
+
+        Renderer {
+            width: width.max(20),
+            input: VecDeque::new(),
+            lines: Vec::new(),
+            cells: Vec::new(),
+            tag: None,
+            kind: LineKind::Text,
+            font: Font::Regular,
+            prev_font: Font::Regular,
+            line_font: None,
+            fill: true,
+            indent: INDENT,
+            hang: 0,
+            prevailing: INDENT,
+            hanging: false,
+            shift: 0,
+            pd: true,
+            stack: Vec::new(),
+            nospace: true,
+            cont: false,
+            pending: Pending::None,
+            strings,
+            macros: HashMap::new(),
+            conds: Vec::new(),
+            expansions: 0,
+            title: None,
+            section: None,
+            heading: String::new(),
+            table: None,
+            mdoc: false,
+            name: None,
+            blocks: Vec::new(),
+            glue: false,
+            spacing: true,
+        }
+    }
+
+    fn finish(mut self) -> Document {
+        self.brk();
+        while self.lines.last().map(|l| l.kind == LineKind::Blank) == Some(true) {
+            self.lines.pop();
+        }
+
+        Document {
+            title: self.title,
+            section: self.section,
+            lines: self.lines,
+        }
+    }
+
+    fn process_line(&mut self, line: &str) {
+        if self.table.is_some() {
+            self.table_line(line);
+        } else if line.starts_with('.') || line.starts_with('\'') {
+            self.request(&line[1..]);
+        } else {
+            self.text_line(line);
+        }
+    }
+
+    // Output
+
+    fn push_line(&mut self, indent: usize, cells: &[Cell], kind: LineKind) {
+        let cells =
+            std::iter::repeat_n((' ', Font::Regular), indent).chain(cells.iter().map(|(c, f)| {
+                if *c == NBSP {
+                    (' ', *f)
+                } else {
+                    (*c, *f)
+                }
+            }));
+
+        self.lines.push(Line::new(cells, kind));
+        self.nospace = false;
+    }
+
+    fn blank(&mut self) {
+        self.brk();
+        if self.nospace || self.lines.is_empty() {
+            return;
+        }
+        if self.lines.last().map(|l| l.kind == LineKind::Blank) == Some(true) {
+            return;
+        }
+        self.lines.push(Line::plain(String::new(), LineKind::Blank));
+    }
+
+    fn brk(&mut self) {
+        while self.cells.last().map(|c| c.0 == ' ') == Some(true) {
+            self.cells.pop();
+        }
+
+        let tag = self.tag.take();
+        if self.cells.is_empty() && tag.is_none() {
+            self.shift = 0;
+            return;
+        }
+
+        let body = self.indent + self.hang;
+        let mut kind = std::mem::replace(&mut self.kind, LineKind::Text);
+        let mut prefix = Vec::new();
+        let mut first = if self.hanging { self.indent } else { body };
+
+        if let Some(mut tag) = tag {
+            kind = LineKind::Tag;
+            first = self.indent;
+
+            let w = width(&tag);
+            if w < self.hang && !self.cells.is_empty() {
+                tag.extend(std::iter::repeat_n((NBSP, Font::Regular), self.hang - w));
+                prefix = tag;
+            } else {
+                self.push_line(self.indent, &tag, LineKind::Tag);
+                kind = LineKind::Text;
+                first = body;
+            }
+        }
+
+        let first = (first as isize + self.shift).max(0) as usize;
+        self.shift = 0;
+        self.hanging = false;
+
+        let cells = std::mem::take(&mut self.cells);
+        if cells.is_empty() {
+            return;
+        }
+        if !self.fill {
+            prefix.extend(cells);
+            self.push_line(first, &prefix, kind);
+            return;
+        }
+
+        let mut line = prefix;
+        let mut indent = first;
+        let mut col = first + width(&line);
+        let mut empty = true;
+
+        for word in cells.split(|c| c.0 == ' ').filter(|w| !w.is_empty()) {
+            let w = width(word);
+            if !empty && col + 1 + w > self.width {
+                self.push_line(indent, &line, kind);
+                kind = LineKind::Text;
+                line = Vec::new();
+                indent = body;
+                col = body;
+                empty = true;
+            }
+            if !empty {
+                line.push((' ', Font::Regular));
+                col += 1;
+            }
+            line.extend_from_slice(word);
+            col += w;
+            empty = false;
+        }
+
+        if !line.is_empty() {
+            self.push_line(indent, &line, kind);
+        }
+    }
+
+    fn sep(&mut self) {
+        if !self.fill || self.cont {
+            return;
+        }
+        if let Some((c, _)) = self.cells.last() {
+            if *c != ' ' {
+                self.cells.push((' ', Font::Regular));
+            }
+        }
+    }
+
+    fn end_input_line(&mut self) {
+        if self.cont {
+            return;
+        }
+
+        match self.pending {
+            Pending::Tag => {
+                self.pending = Pending::None;
+                let mut tag = std::mem::take(&mut self.cells);
+                while tag.last().map(|c| c.0 == ' ') == Some(true) {
+                    tag.pop();
+                }
+                self.tag = Some(tag);
+                return;
+            }
+            Pending::Heading => {
+                self.pending = Pending::None;
+                self.finish_heading();
+                return;
+            }
+            _ => {}
+        }
+
+        if !self.fill {
+            self.brk();
+        }
+    }
+
+    fn text_line(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            if self.fill {
+                self.blank();
+            } else {
+                self.brk();
+                self.lines.push(Line::plain(String::new(), LineKind::Blank));
+            }
+            return;
+        }
+        if line.starts_with(' ') && self.fill && self.pending == Pending::None {
+            self.brk();
+        }
+
+        self.sep();
+        self.cont = false;
+
+        match self.line_font.take() {
+            Some(font) => self.styled(font, line),
+            None => self.push_text(line),
+        }
+
+        if self.mdoc && self.pending == Pending::ItemHead {
+            return;
+        }
+        self.end_input_line();
+    }
+
+    fn styled(&mut self, font: Font, s: &str) {
+        let saved = self.font;
+        self.font = font;
+        self.push_text(s);
+        self.font = saved;
+    }
+
+    fn heading(&mut self, kind: LineKind) {
+        self.brk();
+        self.stack.clear();
+        self.blocks.clear();
+        self.indent = if kind == LineKind::Heading {
+            0
+        } else {
+            SUBINDENT
+        };
+        self.hang = 0;
+        self.prevailing = INDENT;
+        self.fill = true;
+        self.tag = None;
+        self.blank();
+        self.kind = kind;
+        self.font = Font::Bold;
+    }
+
+    fn finish_heading(&mut self) {
+        if self.kind == LineKind::Heading {
+            self.heading = self
+                .cells
+                .iter()
+                .map(|c| if c.0 == NBSP { ' ' } else { c.0 })
+                .collect::<String>()
+                .trim()
+                .to_string();
+        }
+        self.brk();
+        self.font = Font::Regular;
+        self.indent = INDENT;
+        self.nospace = true;
+    }
+
+    fn title_line(&mut self, name: &str, section: &str, center: &str) {
+        self.title = Some(name.to_string());
+        self.section = Some(section.to_string());
+
+        let side = if section.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}({})", name, section)
+        };
+        let used = side.chars().count() * 2 + center.chars().count();
+        let text = if used + 2 <= self.width {
+            let gap = self.width - used;
+            let l = gap / 2;
+            format!(
+                "{side}{l}{center}{r}{side}",
+                side = side,
+                center = center,
+                l = " ".repeat(l),
+                r = " ".repeat(gap - l)
+            )
+        } else {
+            side
+        };
+
+        self.lines.push(Line::plain(text, LineKind::Title));
+        self.nospace = false;
+    }
+
+    // Escapes
+
+    fn push_text(&mut self, s: &str) {
+        let chars = s.chars().collect::<Vec<_>>();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+
+            if c != '\\' {
+                match c {
+                    '\t' => self.cells.push((' ', self.font)),
+                    _ => self.cells.push((c, self.font)),
+                }
+                continue;
+            }
+            if i >= chars.len() {
+                break;
+            }
+
+            let e = chars[i];
+            i += 1;
+            match e {
+                '\\' | 'e' | 'E' => self.cells.push(('\\', self.font)),
+                '-' => self.cells.push(('-', self.font)),
+                ' ' | '~' | '0' => self.cells.push((NBSP, self.font)),
+                't' => self.cells.push((' ', self.font)),
+                '\'' | '`' | '.' => self.cells.push((e, self.font)),
+                'c' => self.cont = true,
+                '"' | '#' => break,
+                'f' => {
+                    let name = read_name(&chars, &mut i);
+                    self.set_font(&name);
+                }
+                '(' | '[' => {
+                    i -= 1;
+                    let name = read_name(&chars, &mut i);
+                    let s = special(&name);
+                    for ch in s.chars() {
+                        self.cells.push((ch, self.font));
+                    }
+                }
+                '*' => {
+                    let name = read_name(&chars, &mut i);
+                    let name = name.split_whitespace().next().unwrap_or("").to_string();
+                    if let Some(value) = self.strings.get(&name).cloned() {
+                        if self.expansions < MAX_EXPANSION {
+                            self.expansions += 1;
+                            self.push_text(&value);
+                        }
+                    }
+                }
+                'n' => {
+                    if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                        i += 1;
+                    }
+                    read_name(&chars, &mut i);
+                }
+                's' => {
+                    if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                        i += 1;
+                    }
+                    if i < chars.len() && (chars[i] == '(' || chars[i] == '[') {
+                        read_name(&chars, &mut i);
+                    } else if i < chars.len() && chars[i] == '\'' {
+                        read_delimited(&chars, &mut i);
+                    } else {
+                        let mut n = 0;
+                        while i < chars.len() && chars[i].is_ascii_digit() && n < 2 {
+                            i += 1;
+                            n += 1;
+                        }
+                    }
+                }
+                'h' => {
+                    let arg = read_delimited(&chars, &mut i);
+                    let n = units(&arg).unwrap_or(0.0).round() as isize;
+                    if n > 0 {
+                        for _ in 0..n {
+                            self.cells.push((NBSP, self.font));
+                        }
+                    } else if n < 0 && self.cells.is_empty() {
+                        self.shift += n;
+                    }
+                }
+                'N' => {
+                    let arg = read_delimited(&chars, &mut i);
+                    if let Some(ch) = arg.parse::<u32>().ok().and_then(std::char::from_u32) {
+                        self.cells.push((ch, self.font));
+                    }
+                }
+                'v' | 'w' | 'o' | 'b' | 'l' | 'L' | 'D' | 'X' | 'Z' | 'A' | 'B' | 'R' | 'S'
+                | 'x' => {
+                    read_delimited(&chars, &mut i);
+                }
+                'k' | 'm' | 'M' | 'F' | 'g' | 'Y' | 'V' | 'O' | '$' => {
+                    read_name(&chars, &mut i);
+                }
+                '|' | '^' | '&' | ')' | '%' | ':' | '/' | ',' | 'a' | 'd' | 'u' | 'r' | 'p'
+                | '{' | '}' | 'z' => {}
+                _ => self.cells.push((e, self.font)),
+            }
+        }
+    }
+
+    fn set_font(&mut self, name: &str) {
+        let font = match name {
+            "B" | "3" | "CB" | "BI" | "4" | "CBI" | "TB" | "HB" => Font::Bold,
+            "I" | "2" | "CI" | "TI" | "HI" => Font::Italic,
+            "P" | "" => self.prev_font,
+            _ => Font::Regular,
+        };
+        self.prev_font = self.font;
+        self.font = font;
+    }
+
+    fn render_cells(&mut self, s: &str) -> String {
+        let saved = std::mem::take(&mut self.cells);
+        let cont = self.cont;
+        self.push_text(s);
+        self.cont = cont;
+        let cells = std::mem::replace(&mut self.cells, saved);
+
+        cells
+            .into_iter()
+            .map(|(c, _)| if c == NBSP { ' ' } else { c })
+            .collect()
+    }
+
+    // Requests
+
+    fn request(&mut self, body: &str) {
+        let body = body.trim_start();
+        if body.starts_with("\\\"") || body.starts_with("\\#") || body.starts_with("\\}") {
+            return;
+        }
+
+        let (name, rest) = match body.find([' ', '\t']) {
+            Some(n) => (&body[..n], body[n..].trim_start()),
+            None => (body, ""),
+        };
+
+        if self.mdoc_request(name, rest) {
+            return;
+        }
+
+        let args = parse_args(rest);
+        match name {
+            "" => {}
+            "TH" => {
+                let name = args
+                    .first()
+                    .map(|s| self.render_cells(s))
+                    .unwrap_or_default();
+                let sec = args
+                    .get(1)
+                    .map(|s| self.render_cells(s))
+                    .unwrap_or_default();
+                let center = args
+                    .get(4)
+                    .map(|s| self.render_cells(s))
+                    .unwrap_or_default();
+                self.title_line(&name, &sec, &center);
+            }
+            "SH" | "SS" => {
+                let kind = if name == "SH" {
+                    LineKind::Heading
+                } else {
+                    LineKind::SubHeading
+                };
+                self.heading(kind);
+                if args.is_empty() {
+                    self.pending = Pending::Heading;
+                } else {
+                    self.push_text(&args.join(" "));
+                    self.finish_heading();
+                }
+            }
+            "PP" | "P" | "LP" => {
+                self.paragraph();
+            }
+            "TP" | "TQ" => {
+                if name == "TP" {
+                    self.paragraph();
+                    if let Some(n) = args.first().and_then(|a| units(a)) {
+                        self.prevailing = n.max(0.0).round() as usize;
+                    }
+                } else {
+                    self.brk();
+                }
+                self.hang = self.prevailing;
+                self.pending = Pending::Tag;
+            }
+            "IP" => {
+                self.paragraph();
+                if let Some(n) = args.get(1).and_then(|a| units(a)) {
+                    self.prevailing = n.max(0.0).round() as usize;
+                }
+                self.hang = self.prevailing;
+                if let Some(tag) = args.first() {
+                    let saved = std::mem::take(&mut self.cells);
+                    self.push_text(tag);
+                    let tag = std::mem::replace(&mut self.cells, saved);
+                    if !tag.is_empty() {
+                        self.tag = Some(tag);
+                    }
+                }
+            }
+            "HP" => {
+                self.paragraph();
+                if let Some(n) = args.first().and_then(|a| units(a)) {
+                    self.prevailing = n.max(0.0).round() as usize;
+                }
+                self.hang = self.prevailing;
+                self.hanging = true;
+            }
+            "RS" => {
+                self.brk();
+                self.stack.push((self.indent, self.hang, self.prevailing));
+                let n = args
+                    .first()
+                    .and_then(|a| units(a))
+                    .map(|n| n.max(0.0).round() as usize)
+                    .unwrap_or(self.prevailing);
+                self.indent += n;
+                self.hang = 0;
+                self.prevailing = INDENT;
+            }
+            "RE" => {
+                self.brk();
+                let level = args.first().and_then(|a| a.parse::<usize>().ok());
+                let keep = match level {
+                    Some(n) if n > 0 => n - 1,
+                    _ => self.stack.len().saturating_sub(1),
+                };
+                while self.stack.len() > keep {
+                    if let Some((indent, hang, prevailing)) = self.stack.pop() {
+                        self.indent = indent;
+                        self.hang = hang;
+                        self.prevailing = prevailing;
+                    }
+                }
+            }
+            "B" | "I" | "SB" => {
+                let font = if name == "I" {
+                    Font::Italic
+                } else {
+                    Font::Bold
+                };
+                if args.is_empty() {
+                    self.line_font = Some(font);
+                } else {
+                    self.sep();
+                    self.cont = false;
+                    self.styled(font, &args.join(" "));
+                    self.end_input_line();
+                }
+            }
+            "SM" | "R" => {
+                if !args.is_empty() {
+                    self.sep();
+                    self.cont = false;
+                    self.styled(Font::Regular, &args.join(" "));
+                    self.end_input_line();
+                }
+            }
+            "BR" | "BI" | "IB" | "IR" | "RB" | "RI" => {
+                let font = |c: char| match c {
+                    'B' => Font::Bold,
+                    'I' => Font::Italic,
+                    _ => Font::Regular,
+                };
+                let mut fonts = name.chars().map(font);
+                let (a, b) = (fonts.next().unwrap(), fonts.next().unwrap());
+
+                self.sep();
+                self.cont = false;
+                for (i, arg) in args.iter().enumerate() {
+                    self.styled(if i % 2 == 0 { a } else { b }, arg);
+                }
+                self.end_input_line();
+            }
+            "br" => self.brk(),
+            "sp" => {
+                let n = args.first().and_then(|a| units(a)).unwrap_or(1.0);
+                if n >= 1.0 {
+                    self.blank();
+                } else {
+                    self.brk();
+                }
+            }
+            "nf" | "EX" => {
+                self.brk();
+                self.fill = false;
+            }
+            "fi" | "EE" => {
+                self.brk();
+                self.fill = true;
+            }
+            "PD" => {
+                self.pd = args.first().map(|a| units(a) != Some(0.0)).unwrap_or(true);
+            }
+            "in" => {
+                self.brk();
+                self.indent = relative(self.indent, args.first());
+            }
+            "ti" => {
+                self.brk();
+                let n = relative(self.indent, args.first()) as isize;
+                self.shift = n - (self.indent + self.hang) as isize;
+            }
+            "ft" => {
+                let f = args.first().cloned().unwrap_or_default();
+                self.set_font(&f);
+            }
+            "ce" | "bp" | "ne" => self.brk(),
+            "ds" | "ds1" | "as" | "as1" => {
+                if let Some(n) = rest.find([' ', '\t']) {
+                    let key = rest[..n].to_string();
+                    let value = rest[n..].trim_start();
+                    let value = value.strip_prefix('"').unwrap_or(value).to_string();
+                    if name.starts_with("as") {
+                        self.strings.entry(key).or_default().push_str(&value);
+                    } else {
+                        self.strings.insert(key, value);
+                    }
+                } else if !rest.is_empty() {
+                    self.strings.insert(rest.to_string(), String::new());
+                }
+            }
+            "de" | "de1" | "am" | "am1" => {
+                let end = args.get(1).map(|e| format!(".{}", e));
+                let body = self.read_until(end.as_deref());
+                if let Some(key) = args.first() {
+                    let entry = self.macros.entry(key.clone()).or_default();
+                    if !name.starts_with("am") {
+                        entry.clear();
+                    }
+                    entry.extend(body);
+                }
+            }
+            "ig" => {
+                let end = args.first().map(|e| format!(".{}", e));
+                self.read_until(end.as_deref());
+            }
+            "rm" => {
+                for a in &args {
+                    self.macros.remove(a);
+                    self.strings.remove(a);
+                }
+            }
+            "if" => {
+                let (truth, rest) = self.condition(rest);
+                self.conditional(truth, &rest);
+            }
+            "ie" => {
+                let (truth, rest) = self.condition(rest);
+                self.conds.push(truth);
+                self.conditional(truth, &rest);
+            }
+            "el" => {
+                let truth = !self.conds.pop().unwrap_or(true);
+                self.conditional(truth, rest);
+            }
+            "do" => self.request(rest),
+            "nx" | "ex" | "ab" => self.input.clear(),
+            "SY" => {
+                self.brk();
+                self.stack.push((self.indent, self.hang, self.prevailing));
+                let cmd = args.first().cloned().unwrap_or_default();
+                let w = self.render_cells(&cmd).chars().count();
+                self.styled(Font::Bold, &cmd);
+                self.hang = w + 1;
+                self.hanging = true;
+            }
+            "OP" => {
+                self.sep();
+                self.push_text("[");
+                if let Some(flag) = args.first() {
+                    self.styled(Font::Bold, flag);
+                }
+                if let Some(arg) = args.get(1) {
+                    self.push_text(" ");
+                    self.styled(Font::Italic, arg);
+                }
+                self.push_text("]");
+            }
+            "YS" => {
+                self.brk();
+                if let Some((indent, hang, prevailing)) = self.stack.pop() {
+                    self.indent = indent;
+                    self.hang = hang;
+                    self.prevailing = prevailing;
+                }
+            }
+            "UR" | "MT" => {
+                let url = args.first().cloned().unwrap_or_default();
+                self.strings.insert("\u{0}url".to_string(), url);
+            }
+            "UE" | "ME" => {
+                if let Some(url) = self.strings.remove("\u{0}url") {
+                    self.sep();
+                    self.push_text(&format!("<{}>", url));
+                    if let Some(p) = args.first() {
+                        self.push_text(p);
+                    }
+                }
+            }
+            "TS" => {
+                self.brk();
+                self.table = Some(Table {
+                    stage: TableStage::Options,
+                    tab: '\t',
+                    rows: Vec::new(),
+                    block: None,
+                });
+            }
+            _ => {
+                if let Some(body) = self.macros.get(name).cloned() {
+                    self.expand(&body, &args);
+                }
+            }
+        }
+    }
+
+    fn paragraph(&mut self) {
+        self.brk();
+        if self.pd {
+            self.blank();
+        }
+        self.hang = 0;
+        self.hanging = false;
+        self.prevailing = INDENT;
+        self.font = Font::Regular;
+        self.pending = Pending::None;
+    }
+
+    fn read_until(&mut self, end: Option<&str>) -> Vec<String> {
+        let end = end.unwrap_or("..");
+        let mut body = Vec::new();
+
+        while let Some(line) = self.input.pop_front() {
+            if line.trim() == end || (end == ".." && line.trim_end() == "..") {
+                break;
+            }
+            body.push(line);
+        }
+        body
+    }
+
+    fn expand(&mut self, body: &[String], args: &[String]) {
+        if self.expansions >= MAX_EXPANSION {
+            return;
+        }
+        self.expansions += 1;
+
+        let mut lines = Vec::new();
+        for line in body {
+            let mut s = line.replace("\\\\", "\\");
+            for n in (1..10).rev() {
+                let value = args.get(n - 1).map(String::as_str).unwrap_or("");
+                s = s.replace(&format!("\\${}", n), value);
+                s = s.replace(&format!("\\$({:02})", n), value);
+            }
+            s = s.replace("\\$*", &args.join(" "));
+            s = s.replace("\\$@", &args.join(" "));
+            s = s.replace("\\$#", &args.len().to_string());
+            lines.push(s);
+        }
+
+        for line in lines.into_iter().rev() {
+            self.input.push_front(line);
+        }
+    }
+
+    fn condition(&self, s: &str) -> (bool, String) {
+        let s = s.trim_start();
+        let (negate, s) = match s.strip_prefix('!') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let chars = s.chars().collect::<Vec<_>>();
+        let mut i = 0;
+
+        let truth = match chars.first() {
+            None => false,
+            Some('n') => {
+                i = 1;
+                true
+            }
+            Some('t') | Some('o') | Some('e') | Some('v') => {
+                i = 1;
+                false
+            }
+            Some(c)
+                if (*c == 'd' || *c == 'r' || *c == 'c' || *c == 'm' || *c == 'F' || *c == 'S')
+                    && chars.get(1) == Some(&' ') =>
+            {
+                i = 2;
+                while i < chars.len() && chars[i] == ' ' {
+                    i += 1;
+                }
+                let start = i;
+                while i < chars.len() && chars[i] != ' ' {
+                    i += 1;
+                }
+                let name = chars[start..i].iter().collect::<String>();
+                match c {
+                    'd' => self.strings.contains_key(&name) || self.macros.contains_key(&name),
+                    'c' => true,
+                    _ => false,
+                }
+            }
+            Some(c) if *c == '\'' || *c == '"' || *c == '|' || *c == '/' => {
+                let delim = *c;
+                i = 1;
+                let mut parts = Vec::new();
+                for _ in 0..2 {
+                    let start = i;
+                    while i < chars.len() && chars[i] != delim {
+                        i += 1;
+                    }
+                    parts.push(chars[start..i.min(chars.len())].iter().collect::<String>());
+                    i += 1;
+                }
+                parts[0] == parts[1]
+            }
+            _ => {
+                let start = i;
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        ' ' | '\t' if depth <= 0 => break,
+                        '\\' if chars.get(i + 1) == Some(&'{') && depth <= 0 => break,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                let expr = chars[start..i].iter().collect::<String>();
+                evaluate(&expr) > 0.0
+            }
+        };
+
+        let rest = chars[i.min(chars.len())..].iter().collect::<String>();
+        (truth != negate, rest)
+    }
+
+    fn conditional(&mut self, truth: bool, rest: &str) {
+        let rest = rest.trim_start();
+
+        if let Some(body) = rest.strip_prefix("\\{") {
+            if truth {
+                let body = body.trim_start();
+                let body = body.strip_suffix("\\}").unwrap_or(body);
+                if !body.trim().is_empty() {
+                    self.process_line(body);
+                }
+            } else {
+                let mut depth = 1 + braces(body);
+                while depth > 0 {
+                    match self.input.pop_front() {
+                        Some(line) => depth += braces(&line),
+                        None => break,
+                    }
+                }
+            }
+        } else if truth && !rest.is_empty() {
+            self.process_line(rest);
+        }
+    }
+
+    // Tables
+
+    fn table_line(&mut self, line: &str) {
+        let table = match self.table.as_mut() {
+            Some(t) => t,
+            None => return,
+        };
+
+        if let Some(block) = table.block.as_mut() {
+            if let Some(rest) = line.strip_prefix("T}") {
+                let text = table.block.take().unwrap_or_default();
+                if let Some(row) = table.rows.last_mut() {
+                    row.push(text);
+                    let tab = table.tab;
+                    row.extend(
+                        rest.split(tab)
+                            .skip(1)
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>(),
+                    );
+                }
+            } else if !line.starts_with('.') {
+                if !block.is_empty() {
+                    block.push(' ');
+                }
+                block.push_str(line);
+            }
+            return;
+        }
+
+        if line.starts_with(".TE") {
+            self.emit_table();
+            return;
+        }
+        if line.starts_with(".T&") {
+            table.stage = TableStage::Format;
+            return;
+        }
+        if line.starts_with('.') || line.starts_with('\'') {
+            return;
+        }
+
+        match table.stage {
+            TableStage::Options => {
+                table.stage = TableStage::Format;
+                if line.trim_end().ends_with(';') {
+                    if let Some(n) = line.find("tab(") {
+                        if let Some(c) = line[n + 4..].chars().next() {
+                            table.tab = c;
+                        }
+                    }
+                    return;
+                }
+                self.table_line(line);
+            }
+            TableStage::Format => {
+                if line.trim_end().ends_with('.') {
+                    table.stage = TableStage::Data;
+                }
+            }
+            TableStage::Data => {
+                let trimmed = line.trim();
+                if trimmed == "_" || trimmed == "=" {
+                    return;
+                }
+                let mut row = line
+                    .split(table.tab)
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+                if row.last().map(|c| c.trim_end().ends_with("T{")) == Some(true) {
+                    let last = row.pop().unwrap_or_default();
+                    let last = last.trim_end().trim_end_matches("T{").to_string();
+                    if !last.is_empty() {
+                        row.push(last);
+                    }
+                    table.block = Some(String::new());
+                }
+                table.rows.push(row);
+            }
+        }
+    }
+
+    fn emit_table(&mut self) {
+        let table = match self.table.take() {
+            Some(t) => t,
+            None => return,
+        };
+
+        let rows = table
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| self.render_cells(c).trim().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths = (0..columns)
+            .map(|i| {
+                rows.iter()
+                    .filter_map(|r| r.get(i))
+                    .map(|c| str_width(c))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        for row in rows {
+            let mut text = String::new();
+            for (i, cell) in row.iter().enumerate() {
+                text.push_str(cell);
+                if i + 1 < row.len() {
+                    text.push_str(&" ".repeat(widths[i] - str_width(cell) + 2));
+                }
+            }
+            let cells = text.chars().map(|c| (c, Font::Regular)).collect::<Vec<_>>();
+            self.push_line(self.indent + self.hang, &cells, LineKind::Text);
+        }
+    }
+
+    // mdoc
+
+    fn mdoc_request(&mut self, name: &str, rest: &str) -> bool {
+        if name == "Dd" || name == "Dt" {
+            self.mdoc = true;
+        }
+        if !self.mdoc {
+            return false;
+        }
+
+        let rest = if name == "It" && self.list_kind() == Some(ListKind::Column) {
+            rest.split('\t').collect::<Vec<_>>().join(" Ta ")
+        } else {
+            rest.to_string()
+        };
+        let mut tokens = parse_args(&rest).into_iter().collect::<VecDeque<_>>();
+
+        match name {
+            "Dd" | "Os" | "Db" | "Bk" | "Ek" | "Bt" | "Ud" => {}
+            "Dt" => {
+                let title = tokens.pop_front().unwrap_or_default();
+                let section = tokens.pop_front().unwrap_or_default();
+                let manual = match section.as_str() {
+                    "1" => "General Commands Manual",
+                    "2" => "System Calls Manual",
+                    "3" => "Library Functions Manual",
+                    "4" => "Kernel Interfaces Manual",
+                    "5" => "File Formats Manual",
+                    "6" => "Games Manual",
+                    "7" => "Miscellaneous Information Manual",
+                    "8" => "System Manager's Manual",
+                    _ => "",
+                };
+                self.title_line(&title, &section, manual);
+            }
+            "Sh" | "Ss" => {
+                let kind = if name == "Sh" {
+                    LineKind::Heading
+                } else {
+                    LineKind::SubHeading
+                };
+                self.heading(kind);
+                self.glue = false;
+                self.push_text(&tokens.into_iter().collect::<Vec<_>>().join(" "));
+                self.finish_heading();
+            }
+            "Pp" | "Lp" => self.paragraph(),
+            "Nd" => {
+                self.sep();
+                self.glue = false;
+                self.word("-", Font::Regular);
+                self.mdoc_tokens(&mut tokens);
+            }
+            "Bl" => {
+                self.brk();
+                let mut kind = ListKind::Item;
+                let mut width = None;
+                let mut offset = 0;
+                let mut compact = false;
+                let mut columns = Vec::new();
+
+                while let Some(t) = tokens.pop_front() {
+                    match t.as_str() {
+                        "-tag" => kind = ListKind::Tag,
+                        "-hang" => kind = ListKind::Hang,
+                        "-ohang" => kind = ListKind::Ohang,
+                        "-inset" | "-diag" => kind = ListKind::Inset,
+                        "-bullet" => kind = ListKind::Bullet,
+                        "-dash" | "-hyphen" => kind = ListKind::Dash,
+                        "-enum" => kind = ListKind::Enum,
+                        "-item" => kind = ListKind::Item,
+                        "-column" => kind = ListKind::Column,
+                        "-compact" => compact = true,
+                        "-width" => width = tokens.pop_front().map(|w| self.mdoc_width(&w)),
+                        "-offset" => {
+                            offset = tokens
+                                .pop_front()
+                                .map(|w| self.mdoc_offset(&w))
+                                .unwrap_or(0)
+                        }
+                        _ if kind == ListKind::Column => {
+                            columns.push(self.mdoc_width(&t).max(str_width(&t)))
+                        }
+                        _ => {}
+                    }
+                }
+
+                let width = width.unwrap_or(match kind {
+                    ListKind::Bullet | ListKind::Dash => 2,
+                    ListKind::Enum => 4,
+                    ListKind::Tag | ListKind::Hang => 8,
+                    _ => 0,
+                });
+                self.blocks.push(Block {
+                    kind,
+                    width,
+                    count: 0,
+                    compact,
+                    columns,
+                    indent: self.indent,
+                    hang: self.hang,
+                    fill: self.fill,
+                });
+                self.indent += self.hang + offset;
+                self.hang = 0;
+            }
+            "El" | "Ed" => {
+                self.brk();
+                self.tag = None;
+                if let Some(block) = self.blocks.pop() {
+                    self.indent = block.indent;
+                    self.hang = block.hang;
+                    self.fill = block.fill;
+                }
+            }
+            "Bd" => {
+                self.brk();
+                if !tokens.iter().any(|t| t == "-compact") {
+                    self.blank();
+                }
+                let mut offset = 0;
+                let mut fill = self.fill;
+                while let Some(t) = tokens.pop_front() {
+                    match t.as_str() {
+                        "-literal" | "-unfilled" => fill = false,
+                        "-filled" | "-ragged" | "-centered" => fill = true,
+                        "-offset" => {
+                            offset = tokens
+                                .pop_front()
+                                .map(|w| self.mdoc_offset(&w))
+                                .unwrap_or(0)
+                        }
+                        _ => {}
+                    }
+                }
+                self.blocks.push(Block {
+                    kind: ListKind::Display,
+                    width: 0,
+                    count: 0,
+                    compact: true,
+                    columns: Vec::new(),
+                    indent: self.indent,
+                    hang: self.hang,
+                    fill: self.fill,
+                });
+                self.indent += self.hang + offset;
+                self.hang = 0;
+                self.fill = fill;
+            }
+            "It" => self.mdoc_item(&mut tokens),
+            "D1" | "Dl" => {
+                self.brk();
+                let (indent, fill) = (self.indent, self.fill);
+                self.indent += 6;
+                self.fill = name == "D1";
+                self.glue = false;
+                self.mdoc_tokens(&mut tokens);
+                self.brk();
+                self.indent = indent;
+                self.fill = fill;
+            }
+            "Bf" => {
+                self.font = match tokens.front().map(String::as_str) {
+                    Some("-emphasis") | Some("Em") => Font::Italic,
+                    Some("-symbolic") | Some("Sy") => Font::Bold,
+                    _ => Font::Regular,
+                };
+            }
+            "Ef" => self.font = Font::Regular,
+            "Sm" => {
+                self.spacing = match tokens.front().map(String::as_str) {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => !self.spacing,
+                };
+            }
+            "Rs" => {
+                self.brk();
+            }
+            "Re" => {
+                self.word(".", Font::Regular);
+                self.brk();
+            }
+            "Ex" | "Rv" => {
+                let subject = tokens
+                    .iter()
+                    .find(|t| t.as_str() != "-std")
+                    .cloned()
+                    .or_else(|| self.name.clone())
+                    .unwrap_or_default();
+                let text = if name == "Ex" {
+                    format!(
+                        "The {} utility exits 0 on success, and >0 if an error occurs.",
+                        subject
+                    )
+                } else {
+                    format!(
+                        "The {}() function returns the value 0 if successful; \
+                         otherwise the value -1 is returned and the global variable \
+                         errno is set to indicate the error.",
+                        subject
+                    )
+                };
+                self.sep();
+                self.push_text(&text);
+            }
+            _ if name.starts_with('%') => {
+                self.glue = false;
+                self.mdoc_tokens(&mut tokens);
+                self.word(",", Font::Regular);
+            }
+            _ if is_callable(name)
+                || name == "Ft"
+                || name == "Fd"
+                || name == "In"
+                || name == "Vt"
+                || name == "Lb"
+                || name == "St"
+                || name == "Fn"
+                || name == "Fo"
+                || name == "Fc" =>
+            {
+                if self.heading == "SYNOPSIS"
+                    && (name == "Nm"
+                        || name == "Ft"
+                        || name == "Fd"
+                        || name == "In"
+                        || name == "Fo")
+                    && !(name == "Nm" && self.tag.is_some())
+                {
+                    self.brk();
+                    if name == "Nm" {
+                        let nm = tokens
+                            .front()
+                            .filter(|t| !is_callable(t) && !is_punct(t))
+                            .cloned()
+                            .or_else(|| self.name.clone())
+                            .unwrap_or_default();
+                        self.hang = str_width(&nm) + 1;
+                        self.hanging = true;
+                    }
+                }
+                self.sep();
+                self.glue = false;
+                tokens.push_front(name.to_string());
+                self.mdoc_tokens(&mut tokens);
+                if self.pending == Pending::ItemHead && name == "Xc" {
+                    return true;
+                }
+            }
+            _ => return false,
+        }
+
+        if self.pending != Pending::ItemHead {
+            self.end_input_line();
+        }
+        true
+    }
+
+    fn list_kind(&self) -> Option<ListKind> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|b| b.kind != ListKind::Display)
+            .map(|b| b.kind)
+    }
+
+    fn mdoc_width(&mut self, s: &str) -> usize {
+        match s {
+            "indent" => 8,
+            "indent-two" => 16,
+            "Ds" => 8,
+            _ => {
+                if let Some(n) = units(s) {
+                    n.max(0.0).round() as usize
+                } else if is_callable(s) {
+                    10
+                } else {
+                    str_width(&self.render_cells(s)) + 2
+                }
+            }
+        }
+    }
+
+    fn mdoc_offset(&mut self, s: &str) -> usize {
+        match s {
+            "left" | "center" | "right" => 0,
+            "indent" | "Ds" => 6,
+            "indent-two" => 12,
+            _ => units(s)
+                .map(|n| n.max(0.0).round() as usize)
+                .unwrap_or_else(|| str_width(s)),
+        }
+    }
+
+    fn mdoc_item(&mut self, tokens: &mut VecDeque<String>) {
+        self.brk();
+        self.tag = None;
+
+        let block = match self
+            .blocks
+            .iter_mut()
+            .rev()
+            .find(|b| b.kind != ListKind::Display)
+        {
+            Some(b) => b,
+            None => return,
+        };
+        block.count += 1;
+        let (kind, hang, count, compact) = (block.kind, block.width, block.count, block.compact);
+        let columns = block.columns.clone();
+
+        if !compact {
+            self.blank();
+        }
+        self.hang = hang;
+        self.glue = false;
+
+        match kind {
+            ListKind::Tag | ListKind::Hang | ListKind::Ohang => {
+                self.mdoc_tokens(tokens);
+                if self.pending == Pending::ItemHead {
+                    return;
+                }
+                self.item_head();
+            }
+            ListKind::Inset => {
+                self.hang = 0;
+                self.styled(Font::Bold, "");
+                self.mdoc_tokens(tokens);
+            }
+            ListKind::Bullet | ListKind::Dash | ListKind::Enum => {
+                let tag = match kind {
+                    ListKind::Bullet => "\\(bu".to_string(),
+                    ListKind::Dash => "-".to_string(),
+                    _ => format!("{}.", count),
+                };
+                let saved = std::mem::take(&mut self.cells);
+                self.push_text(&tag);
+                self.tag = Some(std::mem::replace(&mut self.cells, saved));
+                self.mdoc_tokens(tokens);
+            }
+            ListKind::Item | ListKind::Display => {
+                self.hang = 0;
+                self.mdoc_tokens(tokens);
+            }
+            ListKind::Column => {
+                self.hang = 0;
+                let mut col = 0;
+                let mut start = 0;
+                let mut current = VecDeque::new();
+                let mut flush = |r: &mut Renderer, current: &mut VecDeque<String>, col: usize| {
+                    r.glue = false;
+                    r.mdoc_tokens(current);
+                    let w = width(&r.cells) - start;
+                    let target = columns.get(col).cloned().unwrap_or(w) + 2;
+                    for _ in w..target {
+                        r.cells.push((NBSP, Font::Regular));
+                    }
+                    start = width(&r.cells);
+                };
+                while let Some(t) = tokens.pop_front() {
+                    if t == "Ta" {
+                        flush(self, &mut current, col);
+                        col += 1;
+                    } else {
+                        current.push_back(t);
+                    }
+                }
+                flush(self, &mut current, col);
+                let saved = self.fill;
+                self.fill = false;
+                self.brk();
+                self.fill = saved;
+            }
+        }
+    }
+
+    fn item_head(&mut self) {
+        let kind = self.list_kind();
+        self.pending = Pending::None;
+
+        let mut head = std::mem::take(&mut self.cells);
+        while head.last().map(|c| c.0 == ' ') == Some(true) {
+            head.pop();
+        }
+
+        if kind == Some(ListKind::Ohang) {
+            self.push_line(self.indent, &head, LineKind::Tag);
+            self.hang = 0;
+        } else if kind == Some(ListKind::Hang) {
+            head.push((' ', Font::Regular));
+            self.cells = head;
+            self.hanging = true;
+            self.kind = LineKind::Tag;
+        } else {
+            self.tag = Some(head);
+        }
+    }
+
+    fn word(&mut self, w: &str, font: Font) {
+        let close = is_close(w);
+        if !close && !self.glue && self.spacing && !self.cont {
+            if let Some((c, _)) = self.cells.last() {
+                if *c != ' ' {
+                    self.cells.push((' ', Font::Regular));
+                }
+            }
+        }
+        self.cont = false;
+        self.glue = is_open(w);
+        self.styled(font, w);
+    }
+
+    fn mdoc_tokens(&mut self, tokens: &mut VecDeque<String>) {
+        while let Some(t) = tokens.pop_front() {
+            if is_callable(&t) {
+                self.mdoc_macro(&t, tokens);
+            } else {
+                self.word(&t, Font::Regular);
+            }
+        }
+    }
+
+    fn mdoc_macro(&mut self, name: &str, tokens: &mut VecDeque<String>) {
+        match name {
+            "Fl" => {
+                let args = take_args(tokens);
+                if args.is_empty() {
+                    let glued = tokens.front().map(|t| is_close(t)) == Some(false)
+                        && tokens.front().map(|t| is_callable(t)) == Some(false);
+                    self.word("-", Font::Bold);
+                    self.glue = glued;
+                } else {
+                    for a in args {
+                        self.word(&format!("-{}", a), Font::Bold);
+                    }
+                }
+            }
+            "Ar" => {
+                let args = take_args(tokens);
+                if args.is_empty() {
+                    self.word("file ...", Font::Italic);
+                } else {
+                    for a in args {
+                        self.word(&a, Font::Italic);
+                    }
+                }
+            }
+            "Nm" => {
+                let args = take_args(tokens);
+                if args.is_empty() {
+                    let name = self.name.clone().unwrap_or_default();
+                    self.word(&name, Font::Bold);
+                } else {
+                    if self.name.is_none() {
+                        self.name = Some(args[0].clone());
+                    }
+                    for a in args {
+                        self.word(&a, Font::Bold);
+                    }
+                }
+            }
+            "Cm" | "Ic" | "Sy" | "Cd" | "Fd" => {
+                for a in take_args(tokens) {
+                    self.word(&a, Font::Bold);
+                }
+            }
+            "In" => {
+                let args = take_args(tokens);
+                let header = args.first().cloned().unwrap_or_default();
+                self.word(&format!("#include <{}>", header), Font::Bold);
+            }
+            "Em" | "Pa" | "Va" | "Fa" | "Ft" | "Vt" | "Ad" | "Ev" | "Dv" | "Er" | "Mt" | "Tn"
+            | "Ms" | "Li" | "No" | "Lb" | "Sx" => {
+                let font = match name {
+                    "Em" | "Pa" | "Va" | "Fa" | "Ft" | "Vt" | "Ad" | "Mt" => Font::Italic,
+                    _ => Font::Regular,
+                };
+                for a in take_args(tokens) {
+                    self.word(&a, font);
+                }
+            }
+            "Xr" => {
+                let args = take_args(tokens);
+                let text = match (args.first(), args.get(1)) {
+                    (Some(n), Some(s)) => format!("{}({})", n, s),
+                    (Some(n), None) => n.clone(),
+                    _ => String::new(),
+                };
+                self.word(&text, Font::Regular);
+            }
+            "Lk" => {
+                let args = take_args(tokens);
+                if let Some(url) = args.first() {
+                    if args.len() > 1 {
+                        self.word(&args[1..].join(" "), Font::Regular);
+                        self.word(":", Font::Regular);
+                    }
+                    self.word(url, Font::Regular);
+                }
+            }
+            "Fn" => {
+                let args = take_args(tokens);
+                if let Some(f) = args.first() {
+                    self.word(f, Font::Bold);
+                    self.glue = true;
+                    self.word("(", Font::Regular);
+                    for (i, a) in args[1..].iter().enumerate() {
+                        if i > 0 {
+                            self.word(",", Font::Regular);
+                        }
+                        self.word(a, Font::Italic);
+                    }
+                    self.word(")", Font::Regular);
+                }
+            }
+            "Fo" => {
+                let args = take_args(tokens);
+                if let Some(f) = args.first() {
+                    self.word(f, Font::Bold);
+                    self.glue = true;
+                    self.word("(", Font::Regular);
+                }
+            }
+            "Fc" => self.word(")", Font::Regular),
+            "St" => {
+                for a in take_args(tokens) {
+                    let text = standard(&a);
+                    self.word(text.unwrap_or(&a), Font::Regular);
+                }
+            }
+            "At" | "Bsx" | "Bx" | "Dx" | "Fx" | "Nx" | "Ox" | "Ux" => {
+                let os = match name {
+                    "At" => "AT&T UNIX",
+                    "Bsx" => "BSD/OS",
+                    "Bx" => "BSD",
+                    "Dx" => "DragonFly",
+                    "Fx" => "FreeBSD",
+                    "Nx" => "NetBSD",
+                    "Ox" => "OpenBSD",
+                    _ => "UNIX",
+                };
+                let args = take_args(tokens);
+                let mut text = os.to_string();
+                if let Some(v) = args.first() {
+                    text = format!("{} {}", text, v);
+                }
+                self.word(&text, Font::Regular);
+            }
+            "Ns" => self.glue = true,
+            "Ap" => {
+                self.word("'", Font::Regular);
+                self.glue = true;
+                if let Some(c) = self.cells.last().cloned() {
+                    if c.0 == ' ' {
+                        self.cells.pop();
+                    }
+                }
+                let len = self.cells.len();
+                if len >= 2 && self.cells[len - 2].0 == ' ' {
+                    self.cells.remove(len - 2);
+                }
+            }
+            "Pf" => {
+                if let Some(p) = tokens.pop_front() {
+                    self.word(&p, Font::Regular);
+                    self.glue = true;
+                }
+            }
+            "Ta" => {
+                self.word("  ", Font::Regular);
+            }
+            "Aq" | "Bq" | "Brq" | "Dq" | "Pq" | "Qq" | "Sq" | "Ql" | "Op" => {
+                let (open, close) = quotes(name);
+                let mut trailing = VecDeque::new();
+                while tokens.back().map(|t| is_close(t)) == Some(true) && tokens.len() > 1 {
+                    if let Some(t) = tokens.pop_back() {
+                        trailing.push_front(t);
+                    }
+                }
+                self.word(open, Font::Regular);
+                self.glue = true;
+                self.mdoc_tokens(tokens);
+                self.glue = true;
+                self.word(close, Font::Regular);
+                self.mdoc_tokens(&mut trailing);
+            }
+            "Ao" | "Bo" | "Bro" | "Do" | "Po" | "Qo" | "So" | "Oo" | "Eo" => {
+                let open = match name {
+                    "Eo" => tokens.pop_front().unwrap_or_default(),
+                    _ => quotes(&format!("{}q", &name[..name.len() - 1]))
+                        .0
+                        .to_string(),
+                };
+                self.word(&open, Font::Regular);
+                self.glue = true;
+            }
+            "Ac" | "Bc" | "Brc" | "Dc" | "Pc" | "Qc" | "Sc" | "Oc" | "Ec" => {
+                let close = match name {
+                    "Ec" => tokens.pop_front().unwrap_or_default(),
+                    _ => quotes(&format!("{}q", &name[..name.len() - 1]))
+                        .1
+                        .to_string(),
+                };
+                self.word(&close, Font::Regular);
+            }
+            "Xo" => {
+                if self.list_kind().is_some() && self.tag.is_none() && self.pending == Pending::None
+                {
+                    self.pending = Pending::ItemHead;
+                }
+            }
+            "Xc" => {
+                if self.pending == Pending::ItemHead {
+                    self.item_head();
+                }
+            }
+            _ => {
+                for a in take_args(tokens) {
+                    self.word(&a, Font::Regular);
+                }
+            }
+        }
+    }
+}
+
+fn join_continuations(source: &str) -> VecDeque<String> {
+    let mut lines = VecDeque::new();
+    let mut current = String::new();
+
+    for line in source.lines() {
+        let trailing = line.chars().rev().take_while(|c| *c == '\\').count();
+        if trailing % 2 == 1 {
+            current.push_str(&line[..line.len() - 1]);
+        } else {
+            current.push_str(line);
+            lines.push_back(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        lines.push_back(current);
+    }
+    lines
+}
+
+fn parse_args(s: &str) -> Vec<String> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut args = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+            i += 1;
+        }
+        if i >= chars.len() {
+            break;
+        }
+
+        let mut arg = String::new();
+        if chars[i] == '"' {
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == '"' {
+                    if chars.get(i + 1) == Some(&'"') {
+                        arg.push('"');
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    arg.push(chars[i]);
+                    i += 1;
+                }
+                arg.push(chars[i]);
+                i += 1;
+            }
+        } else {
+            while i < chars.len() && chars[i] != ' ' && chars[i] != '\t' {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    if chars[i + 1] == '"' {
+                        if !arg.is_empty() {
+                            args.push(arg);
+                        }
+                        return args;
+                    }
+                    arg.push(chars[i]);
+                    i += 1;
+                }
+                arg.push(chars[i]);
+                i += 1;
+            }
+        }
+        args.push(arg);
+    }
+
+    args
+}
+
+fn read_name(chars: &[char], i: &mut usize) -> String {
+    match chars.get(*i) {
+        Some('(') => {
+            let name = chars[(*i + 1).min(chars.len())..(*i + 3).min(chars.len())]
+                .iter()
+                .collect();
+            *i = (*i + 3).min(chars.len());
+            name
+        }
+        Some('[') => {
+            let start = *i + 1;
+            let mut end = start;
+            while end < chars.len() && chars[end] != ']' {
+                end += 1;
+            }
+            *i = (end + 1).min(chars.len());
+            chars[start..end].iter().collect()
+        }
+        Some(c) => {
+            *i += 1;
+            c.to_string()
+        }
+        None => String::new(),
+    }
+}
+
+fn read_delimited(chars: &[char], i: &mut usize) -> String {
+    let delim = match chars.get(*i) {
+        Some(c) => *c,
+        None => return String::new(),
+    };
+    let start = *i + 1;
+    let mut end = start;
+    while end < chars.len() && chars[end] != delim {
+        end += 1;
+    }
+    *i = (end + 1).min(chars.len());
+    chars[start..end].iter().collect()
+}
+
+fn braces(s: &str) -> isize {
+    s.matches("\\{").count() as isize - s.matches("\\}").count() as isize
+}
+
+fn units(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (num, unit) =
+        match s.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+')) {
+            Some(n) => (&s[..n], &s[n..]),
+            None => (s, ""),
+        };
+    let n = num.parse::<f64>().ok()?;
+
+    let scale = match unit {
+        "" | "n" | "m" | "v" | "M" => 1.0,
+        "i" => 10.0,
+        "c" => 4.0,
+        "p" => 1.0 / 7.0,
+        "P" => 10.0 / 6.0,
+        "u" => 1.0 / 24.0,
+        _ => return None,
+    };
+    Some(n * scale)
+}
+
+fn relative(current: usize, arg: Option<&String>) -> usize {
+    match arg {
+        None => INDENT,
+        Some(a) => {
+            let n = units(a).unwrap_or(0.0).round() as isize;
+            let v = if a.starts_with('+') || a.starts_with('-') {
+                current as isize + n
+            } else {
+                n
+            };
+            v.max(0) as usize
+        }
+    }
+}
+
+fn evaluate(expr: &str) -> f64 {
+    let expr = expr.trim_matches(|c| c == '(' || c == ')');
+    for op in &[">=", "<=", "==", "=", ">", "<", "&", ":"] {
+        if let Some(n) = expr.find(op) {
+            let (a, b) = (evaluate(&expr[..n]), evaluate(&expr[n + op.len()..]));
+            let truth = match *op {
+                ">=" => a >= b,
+                "<=" => a <= b,
+                "==" | "=" => (a - b).abs() < f64::EPSILON,
+                ">" => a > b,
+                "<" => a < b,
+                "&" => a > 0.0 && b > 0.0,
+                _ => a > 0.0 || b > 0.0,
+            };
+            return if truth { 1.0 } else { 0.0 };
+        }
+    }
+    // Number registers are never set, so `\n(.g` and friends are zero.
+    if expr.starts_with("\\n") {
+        return 0.0;
+    }
+    units(expr).unwrap_or(0.0)
+}
+
+fn special(name: &str) -> String {
+    let s = match name {
+        "em" => "—",
+        "en" => "–",
+        "hy" | "mi" | "-" | "-D" => "-",
+        "aq" => "'",
+        "dq" => "\"",
+        "lq" => "“",
+        "rq" => "”",
+        "oq" => "‘",
+        "cq" => "’",
+        "Bq" => "„",
+        "bq" => "‚",
+        "Fo" => "«",
+        "Fc" => "»",
+        "fo" => "‹",
+        "fc" => "›",
+        "bu" => "•",
+        "co" => "©",
+        "rg" => "®",
+        "tm" => "™",
+        "->" => "→",
+        "<-" => "←",
+        "<>" => "↔",
+        "ua" => "↑",
+        "da" => "↓",
+        "=>" => "⇒",
+        "mu" => "×",
+        "di" => "÷",
+        "+-" => "±",
+        "de" => "°",
+        "sc" => "§",
+        "ps" => "¶",
+        "dg" => "†",
+        "dd" => "‡",
+        "ba" | "or" => "|",
+        "br" => "│",
+        "ti" | "ap" => "~",
+        "ha" | "a^" => "^",
+        "at" => "@",
+        "sh" => "#",
+        "Do" => "$",
+        "rs" => "\\",
+        "sl" => "/",
+        "lB" => "[",
+        "rB" => "]",
+        "lC" => "{",
+        "rC" => "}",
+        "la" => "⟨",
+        "ra" => "⟩",
+        "aa" => "´",
+        "ga" => "`",
+        "==" => "≡",
+        "!=" => "≠",
+        "<=" => "≤",
+        ">=" => "≥",
+        "ne" => "≢",
+        "~=" | "~~" => "≈",
+        "if" => "∞",
+        "ct" => "¢",
+        "Po" => "£",
+        "Eu" | "eu" => "€",
+        "Ye" => "¥",
+        "ss" => "ß",
+        "ae" => "æ",
+        "AE" => "Æ",
+        "'e" => "é",
+        "`e" => "è",
+        "'a" => "á",
+        "`a" => "à",
+        ":a" => "ä",
+        ":o" => "ö",
+        ":u" => "ü",
+        ":A" => "Ä",
+        ":O" => "Ö",
+        ":U" => "Ü",
+        "tno" | "no" => "¬",
+        "pl" => "+",
+        "eq" => "=",
+        "sq" => "□",
+        "ci" => "○",
+        "em-dash" => "—",
+        _ => {
+            let code = name
+                .strip_prefix("u")
+                .or_else(|| name.strip_prefix("char"))
+                .and_then(|c| {
+                    if name.starts_with("char") {
+                        c.parse::<u32>().ok()
+                    } else {
+                        u32::from_str_radix(c.split('_').next().unwrap_or(""), 16).ok()
+                    }
+                })
+                .and_then(std::char::from_u32);
+            return code.map(|c| c.to_string()).unwrap_or_default();
+        }
+    };
+    s.to_string()
+}
+
+fn standard(arg: &str) -> Option<&'static str> {
+    let s = match arg {
+        "-ansiC" | "-ansiC-89" => "ANSI X3.159-1989 (“ANSI C89”)",
+        "-isoC" | "-isoC-90" => "ISO/IEC 9899:1990 (“ISO C90”)",
+        "-isoC-99" => "ISO/IEC 9899:1999 (“ISO C99”)",
+        "-isoC-2011" => "ISO/IEC 9899:2011 (“ISO C11”)",
+        "-p1003.1" => "IEEE Std 1003.1 (“POSIX.1”)",
+        "-p1003.1-2001" => "IEEE Std 1003.1-2001 (“POSIX.1”)",
+        "-p1003.1-2004" => "IEEE Std 1003.1-2004 (“POSIX.1”)",
+        "-p1003.1-2008" => "IEEE Std 1003.1-2008 (“POSIX.1”)",
+        "-p1003.2" => "IEEE Std 1003.2 (“POSIX.2”)",
+        "-p1003.2-92" => "IEEE Std 1003.2-1992 (“POSIX.2”)",
+        "-susv2" => "Version 2 of the Single UNIX Specification (“SUSv2”)",
+        "-susv3" => "Version 3 of the Single UNIX Specification (“SUSv3”)",
+        "-susv4" => "Version 4 of the Single UNIX Specification (“SUSv4”)",
+        "-xpg4" => "X/Open Portability Guide Issue 4 (“XPG4”)",
+        "-xpg4.2" => "X/Open Portability Guide Issue 4, Version 2 (“XPG4.2”)",
+        _ => return None,
+    };
+    Some(s)
+}
+
+fn quotes(name: &str) -> (&'static str, &'static str) {
+    match name {
+        "Aq" => ("<", ">"),
+        "Bq" | "Op" | "Oq" => ("[", "]"),
+        "Brq" => ("{", "}"),
+        "Dq" => ("“", "”"),
+        "Pq" => ("(", ")"),
+        "Qq" => ("\"", "\""),
+        _ => ("‘", "’"),
+    }
+}
+
+fn is_callable(s: &str) -> bool {
+    matches!(
+        s,
+        "Ac" | "Ad"
+            | "An"
+            | "Ao"
+            | "Ap"
+            | "Aq"
+            | "Ar"
+            | "At"
+            | "Bc"
+            | "Bo"
+            | "Bq"
+            | "Brc"
+            | "Bro"
+            | "Brq"
+            | "Bsx"
+            | "Bx"
+            | "Cd"
+            | "Cm"
+            | "Dc"
+            | "Do"
+            | "Dq"
+            | "Dv"
+            | "Dx"
+            | "Ec"
+            | "Em"
+            | "Eo"
+            | "Er"
+            | "Ev"
+            | "Fa"
+            | "Fc"
+            | "Fl"
+            | "Fn"
+            | "Fo"
+            | "Ft"
+            | "Fx"
+            | "Ic"
+            | "In"
+            | "Lb"
+            | "Li"
+            | "Lk"
+            | "Ms"
+            | "Mt"
+            | "Nm"
+            | "No"
+            | "Ns"
+            | "Nx"
+            | "Oc"
+            | "Oo"
+            | "Op"
+            | "Ox"
+            | "Pa"
+            | "Pc"
+            | "Pf"
+            | "Po"
+            | "Pq"
+            | "Qc"
+            | "Ql"
+            | "Qo"
+            | "Qq"
+            | "Sc"
+            | "So"
+            | "Sq"
+            | "St"
+            | "Sx"
+            | "Sy"
+            | "Ta"
+            | "Tn"
+            | "Ux"
+            | "Va"
+            | "Vt"
+            | "Xc"
+            | "Xo"
+            | "Xr"
+            | "Fd"
+    )
+}
+
+fn is_close(s: &str) -> bool {
+    matches!(s, "." | "," | ":" | ";" | ")" | "]" | "?" | "!")
+}
+
+fn is_open(s: &str) -> bool {
+    s == "(" || s == "["
+}
+
+fn is_punct(s: &str) -> bool {
+    is_close(s) || is_open(s) || s == "|"
+}
+
+fn take_args(tokens: &mut VecDeque<String>) -> Vec<String> {
+    let mut args = Vec::new();
+    while let Some(t) = tokens.front() {
+        if is_callable(t) || is_punct(t) {
+            break;
+        }
+        args.extend(tokens.pop_front());
+    }
+    args
+}
+
+fn width(cells: &[Cell]) -> usize {
+    cells.iter().map(|(c, _)| c.width().unwrap_or(0)).sum()
+}
+
+fn str_width(s: &str) -> usize {
+    s.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(src: &str) -> Vec<String> {
+        render(src, 80).lines.into_iter().map(|l| l.text).collect()
+    }
+
+    #[test]
+    fn tagged_paragraph() {
+        let doc = render(
+            ".TH LS 1\n.SH OPTIONS\n.TP\n\\fB\\-a\\fR, \\fB\\-\\-all\\fR\ndo not ignore entries\n",
+            80,
+        );
+        let tag = doc.lines.iter().find(|l| l.kind == LineKind::Tag).unwrap();
+
+        assert_eq!("       -a, --all", tag.text);
+        assert_eq!(Some("1".to_string()), doc.section);
+        assert!(doc
+            .lines
+            .iter()
+            .any(|l| l.text == "              do not ignore entries"));
+    }
+
+    #[test]
+    fn short_tag_shares_line() {
+        let out = lines(".SH OPTIONS\n.TP\n.B \\-v\nverbose\n");
+        assert_eq!(vec!["OPTIONS", "       -v     verbose"], out);
+    }
+
+    #[test]
+    fn conditional_branches() {
+        let out = lines(".ie \\n(.g .ds Aq \\(aq\n.el .ds Aq '\n.SH NAME\nit\\*(Aqs\n");
+        assert_eq!(vec!["NAME", "       it's"], out);
+    }
+
+    #[test]
+    fn mdoc_flags() {
+        let out =
+            lines(".Dd May 1, 2020\n.Dt CAT 1\n.Sh SYNOPSIS\n.Nm cat\n.Op Fl belnstuv\n.Ar\n");
+        assert_eq!("       cat [-belnstuv] file ...", out[3]);
+    }
+
+    #[test]
+    fn fonts_become_spans() {
+        let doc = render(
+            ".TH LS 1\n.SH OPTIONS\n.TP\n\\fB\\-a\\fR, \\fB\\-\\-all\\fR \\fIWHEN\\fP\nall\n",
+            80,
+        );
+        let tag = doc.lines.iter().find(|l| l.kind == LineKind::Tag).unwrap();
+
+        assert_eq!("       -a, --all WHEN", tag.text);
+        assert_eq!(
+            vec![
+                (7, 9, Font::Bold),
+                (11, 16, Font::Bold),
+                (17, 21, Font::Italic)
+            ],
+            tag.spans
+                .iter()
+                .map(|s| (s.start, s.end, s.font))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Opt {
+    pub short: Option<String>,
+    pub long: Option<String>,
+    pub aliases: Vec<String>,
+    pub argument: Option<String>,
+    pub optional_argument: bool,
+    pub description: String,
+    pub line: usize,
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+impl Opt {
+    pub fn names(&self) -> Vec<&str> {
+        self.short
+            .iter()
+            .chain(self.long.iter())
+            .chain(self.aliases.iter())
+            .map(String::as_str)
+            .collect()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = split_value(name).0;
+        self.names().contains(&name)
+    }
+
+    pub fn takes_argument(&self) -> bool {
+        self.argument.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Options {
+    options: Vec<Opt>,
+}
+
+impl Options {
+    pub fn parse(lines: &[String]) -> Self {
+        let lines = lines.iter().map(|l| expand_tabs(l)).collect::<Vec<_>>();
+        let mut options = Vec::new();
+        let mut undescribed: Vec<usize> = Vec::new();
+        let mut i = 0;
+
+        while i < lines.len() {
+            let (mut opt, indent, description) = match definition(&lines[i], lines.get(i + 1)) {
+                Some(d) => d,
+                None => {
+                    undescribed.clear();
+                    i += 1;
+                    continue;
+                }
+            };
+            opt.line = i;
+
+            let column = description.as_ref().map(|d| d.0);
+            let mut text = description.into_iter().map(|d| d.1).collect::<Vec<_>>();
+            let mut j = i + 1;
+            while j < lines.len() {
+                let line = &lines[j];
+                if line.trim().is_empty() || indent_of(line) <= indent {
+                    break;
+                }
+                // clap indents options without a short name deeper than the
+                // others, so only text at the description column continues.
+                let nested =
+                    !text.is_empty() && column.map(|c| indent_of(line) >= c) != Some(false);
+                if !nested && definition(line, lines.get(j + 1)).is_some() {
+                    break;
+                }
+                text.push(line.trim().to_string());
+                j += 1;
+            }
+
+            opt.description = text.join(" ");
+            if opt.default.is_none() {
+                opt.default = default_value(&opt.description);
+            }
+            if opt.values.is_empty() {
+                opt.values = possible_values(&opt.description);
+            }
+            if let Some(choices) = opt.argument.as_ref().and_then(|a| a.strip_prefix('{')) {
+                opt.values = choices
+                    .trim_end_matches('}')
+                    .split(',')
+                    .map(String::from)
+                    .collect();
+            }
+            if opt.description.is_empty() {
+                undescribed.push(options.len());
+            } else {
+                // Stacked tags (`-a` on one line, `--all` on the next) share
+                // the description of the last one.
+                for n in undescribed.drain(..) {
+                    let o: &mut Opt = &mut options[n];
+                    o.description = opt.description.clone();
+                }
+            }
+
+            options.push(opt);
+            i = j.max(i + 1);
+        }
+
+        Options { options }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Opt> {
+        self.options.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.options.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Opt> {
+        self.options.iter().find(|o| o.matches(name))
+    }
+}
+
+impl IntoIterator for Options {
+    type Item = Opt;
+    type IntoIter = std::vec::IntoIter<Opt>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.options.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Options {
+    type Item = &'a Opt;
+    type IntoIter = std::slice::Iter<'a, Opt>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.options.iter()
+    }
+}
+
+impl From<Vec<Opt>> for Options {
+    fn from(options: Vec<Opt>) -> Self {
+        Options { options }
+    }
+}
+
+// Splits `--name=value` into its name and value parts.
+pub fn split_value(arg: &str) -> (&str, Option<&str>) {
+    match arg.find('=') {
+        Some(n) if arg.starts_with('-') => (&arg[..n], Some(&arg[n + 1..])),
+        _ => (arg, None),
+    }
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut s = String::with_capacity(line.len());
+    for c in line.chars() {
+        if c == '\t' {
+            let n = 8 - s.chars().count() % 8;
+            s.extend(std::iter::repeat_n(' ', n));
+        } else {
+            s.push(c);
+        }
+    }
+    s
+}
+
+fn indent_of(line: &str) -> usize {
+    line.chars().take_while(|c| c.is_whitespace()).count()
+}
+
+// A description found on the tag line, with the column it starts at.
+type Description = Option<(usize, String)>;
+
+// Recognises lines such as `  -a, --all  do not ignore entries` and returns
+// the option, its indentation and any description on the same line.
+fn definition(line: &str, next: Option<&String>) -> Option<(Opt, usize, Description)> {
+    let indent = indent_of(line);
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut i = indent;
+
+    if chars.get(i) != Some(&'-') {
+        return None;
+    }
+
+    let mut opt = Opt::default();
+    loop {
+        let (names, end) = option_name(&chars, i)?;
+        i = end;
+        for name in names {
+            if name.starts_with("--") {
+                if opt.long.is_none() {
+                    opt.long = Some(name);
+                } else {
+                    opt.aliases.push(name);
+                }
+            } else if opt.short.is_none() {
+                opt.short = Some(name);
+            } else {
+                opt.aliases.push(name);
+            }
+        }
+
+        if let Some((arg, optional, end)) = placeholder(&chars, i) {
+            if opt.argument.is_none() {
+                opt.argument = Some(arg);
+                opt.optional_argument = optional;
+            }
+            i = end;
+        }
+
+        let rest = chars[i..].iter().collect::<String>();
+        let separator = [", ", " | ", ",", "|", " or ", ", or ", " "]
+            .iter()
+            .find(|s| rest.starts_with(**s) && rest[s.len()..].starts_with('-'));
+
+        match separator {
+            Some(s) => i += s.chars().count(),
+            None => break,
+        }
+    }
+
+    let rest = chars[i..].iter().collect::<String>();
+
+    // kubectl prints `--all-namespaces=false:` with the default in place of
+    // a placeholder.
+    if rest.is_empty() {
+        if let Some(value) = opt
+            .argument
+            .as_ref()
+            .and_then(|a| a.strip_suffix(':'))
+            .map(unquote)
+        {
+            opt.argument = None;
+            if value != "true" && value != "false" {
+                opt.argument = opt.long.as_ref().map(|l| l[2..].to_uppercase());
+                opt.default = Some(value).filter(|v| !v.is_empty() && v != "[]");
+            }
+        }
+    }
+
+    if rest.trim().is_empty() {
+        return Some((opt, indent, None));
+    }
+
+    let gap = indent_of(&rest);
+    let aligned = next
+        .filter(|n| !n.trim().is_empty())
+        .map(|n| indent_of(n) == i + gap)
+        .unwrap_or(false);
+    if gap >= 2 || (gap == 1 && aligned) {
+        Some((opt, indent, Some((i + gap, rest.trim().to_string()))))
+    } else {
+        None
+    }
+}
+
+fn option_name(chars: &[char], start: usize) -> Option<(Vec<String>, usize)> {
+    let mut i = start;
+    let mut name = String::new();
+
+    while i < chars.len() && chars[i] == '-' && name.len() < 2 {
+        name.push('-');
+        i += 1;
+    }
+
+    let body = i;
+    let mut negatable = false;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_alphanumeric() || c == '_' || (c == '-' && i > body) || (c == '.' && i > body) {
+            name.push(c);
+            i += 1;
+        } else if chars[i..].starts_with(&['[', 'n', 'o', '-', ']']) && name == "--" {
+            negatable = true;
+            i += 5;
+        } else if (c == '?' || c == '#' || c == '@') && i == body && name == "-" {
+            name.push(c);
+            i += 1;
+            break;
+        } else {
+            break;
+        }
+    }
+
+    let name = name.trim_end_matches(['-', '.']).to_string();
+    if name.len() <= name.chars().take_while(|c| *c == '-').count() {
+        return None;
+    }
+
+    // Short options are a single character, otherwise this is prose or a
+    // bundle such as `-la`.
+    if !name.starts_with("--") && name.chars().count() != 2 && !is_long_single(&name) {
+        return None;
+    }
+
+    let mut names = vec![name.clone()];
+    if negatable {
+        names.push(format!("--no-{}", &name[2..]));
+    }
+    Some((names, i))
+}
+
+// Single dash long options as used by find(1) and Go's flag package.
+fn is_long_single(name: &str) -> bool {
+    name.chars()
+        .skip(1)
+        .all(|c| c.is_ascii_lowercase() || c == '-' || c.is_ascii_digit())
+        && name.chars().nth(1).map(|c| c.is_ascii_lowercase()) == Some(true)
+}
+
+fn placeholder(chars: &[char], start: usize) -> Option<(String, bool, usize)> {
+    let rest = &chars[start..];
+
+    match rest.first()? {
+        '=' => {
+            let (arg, end) = word(chars, start + 1)?;
+            let optional = arg.starts_with('[');
+            Some((trim_brackets(&arg), optional, end))
+        }
+        '[' => {
+            let end = start + rest.iter().position(|c| *c == ']')? + 1;
+            let inner = chars[start + 1..end - 1].iter().collect::<String>();
+            let inner = inner.trim_start_matches('=');
+            Some((trim_brackets(inner), true, end))
+        }
+        '<' => {
+            let end = start + rest.iter().position(|c| *c == '>')? + 1;
+            Some((chars[start..end].iter().collect(), false, end))
+        }
+        ' ' => {
+            let (arg, end) = word(chars, start + 1)?;
+            let after = chars[end..].iter().collect::<String>();
+            let terminated = after.is_empty()
+                || after.starts_with("  ")
+                || after.starts_with(", -")
+                || after.starts_with(" | -")
+                || (after.starts_with(' ') && !after[1..].starts_with(' '));
+            let plausible = arg.starts_with('<')
+                || arg.starts_with('{')
+                || arg.starts_with('[')
+                || arg
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "_-.".contains(c))
+                || after.is_empty()
+                || after.starts_with("  ")
+                || after.starts_with(", -");
+            let punctuated = arg.ends_with([',', ':', ';', ')'])
+                || (arg.ends_with('.') && !arg.ends_with("..."));
+            if terminated && plausible && !punctuated {
+                Some((trim_brackets(&arg), arg.starts_with('['), end))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn word(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let first = *chars.get(start)?;
+    if first == '-' || first.is_whitespace() {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '<' | '[' | '{' | '(' => depth += 1,
+            '>' | ']' | '}' | ')' => depth -= 1,
+            ' ' | ',' | '|' if depth <= 0 => break,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Some((chars[start..i].iter().collect(), i))
+}
+
+// Picks up `[default: 10]` (clap, docopt), `(default: 10)` (argparse) and
+// `(default "x")` (Go's flag package) from a description.
+fn default_value(description: &str) -> Option<String> {
+    for open in &["[default: ", "(default: ", "(default ", "(defaults to "] {
+        if let Some(n) = description.find(open) {
+            let close = if open.starts_with('[') { ']' } else { ')' };
+            let rest = &description[n + open.len()..];
+            let value = rest[..rest.find(close)?].trim();
+            // `(default print headers)` is prose rather than a value.
+            let quoted = value.starts_with(['"', '\'']);
+            if open.ends_with(": ") || quoted || !value.contains(' ') {
+                return Some(unquote(value)).filter(|v| !v.is_empty());
+            }
+        }
+    }
+    None
+}
+
+fn possible_values(description: &str) -> Vec<String> {
+    let open = "[possible values: ";
+    description
+        .find(open)
+        .map(|n| &description[n + open.len()..])
+        .and_then(|rest| rest.find(']').map(|end| &rest[..end]))
+        .map(|values| {
+            values
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn unquote(s: &str) -> String {
+    for q in &['"', '\''] {
+        if s.len() >= 2 && s.starts_with(*q) && s.ends_with(*q) {
+            return s[1..s.len() - 1].to_string();
+        }
+    }
+    s.to_string()
+}
+
+fn trim_brackets(s: &str) -> String {
+    let s = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s);
+    s.trim_start_matches('=').to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn man_options() {
+        let options = Options::parse(&lines(
+            "OPTIONS\n       -a, --all\n              do not ignore entries\n\n       -c     with -lt: sort by ctime\n              newest first\n\n       --color[=WHEN]\n              colorize the output\n",
+        ));
+
+        assert_eq!(3, options.len());
+
+        let all = options.find("--all").unwrap();
+        assert_eq!(Some("-a".to_string()), all.short);
+        assert_eq!("do not ignore entries", all.description);
+        assert_eq!(1, all.line);
+
+        let c = options.find("-c").unwrap();
+        assert_eq!("with -lt: sort by ctime newest first", c.description);
+
+        let color = options.find("--color=auto").unwrap();
+        assert_eq!(Some("WHEN".to_string()), color.argument);
+        assert!(color.optional_argument);
+    }
+
+    #[test]
+    fn arguments_and_negation() {
+        let options = Options::parse(&lines(
+            "       -C <commit>, --reuse-message=<commit>\n           Reuse a message.\n\n       --[no-]status\n           Include status.\n\n  -o, --output FILE   Write to FILE\n",
+        ));
+
+        let reuse = options.find("-C").unwrap();
+        assert_eq!(Some("--reuse-message".to_string()), reuse.long);
+        assert_eq!(Some("<commit>".to_string()), reuse.argument);
+
+        assert!(options.find("--no-status").is_some());
+
+        let output = options.find("--output").unwrap();
+        assert_eq!(Some("FILE".to_string()), output.argument);
+        assert_eq!("Write to FILE", output.description);
+    }
+
+    #[test]
+    fn prose_is_not_an_option() {
+        let options = Options::parse(&lines(
+            "       --interactive or --patch switch), in which the commit will\n",
+        ));
+        assert!(options.is_empty());
+    }
+}
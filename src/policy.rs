@@ -0,0 +1,305 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+use glob::Pattern;
+use serde_derive::Deserialize;
+
+use crate::shell;
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Parse(PathBuf, String),
+    Pattern(String, String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            PolicyError::Pattern(pattern, e) => write!(f, "invalid pattern `{}`: {}", pattern, e),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+// A command (and optionally some of its arguments) given as glob patterns.
+// The command pattern is matched against both the path and its file name;
+// without argument patterns the rule matches any use of the command.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<String>,
+}
+
+impl Rule {
+    fn matches(&self, command: &str, arguments: &[String]) -> Result<bool, Error> {
+        let name = Path::new(command)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(command);
+        let pattern = compile(&self.command)?;
+        if !pattern.matches(command) && !pattern.matches(name) {
+            return Ok(false);
+        }
+        if self.arguments.is_empty() {
+            return Ok(true);
+        }
+
+        for pattern in &self.arguments {
+            let pattern = compile(pattern)?;
+            if arguments.iter().any(|a| pattern.matches(a)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn compile(pattern: &str) -> Result<Pattern, Error> {
+    Pattern::new(pattern)
+        .map_err(|e| PolicyError::Pattern(pattern.to_string(), e.msg.to_string()).into())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Run,
+    Confirm,
+    Refuse,
+}
+
+// What may run without asking. This guards against slips of the Enter key,
+// it is not a sandbox: the user can always run the command themselves.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    // Refuse everything that no `allow` rule matches.
+    #[serde(default)]
+    pub allowlist: bool,
+    #[serde(default)]
+    pub allow: Vec<Rule>,
+    #[serde(default)]
+    pub confirm: Vec<Rule>,
+    // The user's own policy, which can only add to this one.
+    #[serde(skip)]
+    stricter: Vec<Policy>,
+}
+
+const SYSTEM: &str = "/etc/man-with/policy.toml";
+
+impl Policy {
+    pub fn parse(path: &Path, text: &str) -> Result<Self, Error> {
+        let policy: Policy = toml::from_str(text)
+            .map_err(|e| PolicyError::Parse(path.to_path_buf(), e.to_string()))?;
+
+        // Report bad patterns now rather than when something is run.
+        for rule in policy.allow.iter().chain(policy.confirm.iter()) {
+            compile(&rule.command)?;
+            for pattern in &rule.arguments {
+                compile(pattern)?;
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(path, &text)
+    }
+
+    // `/etc/man-with/policy.toml` for everyone on the machine, restricted
+    // further by `path` or `$XDG_CONFIG_HOME/man-with/policy.toml`. The
+    // user's policy can't allow what the machine's refuses.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Error> {
+        let system = Path::new(SYSTEM);
+        let mut policy = if system.is_file() {
+            Self::read(system)?
+        } else {
+            Self::default()
+        };
+
+        let user = path.or_else(|| {
+            dirs::config_dir()
+                .map(|dir| dir.join("man-with").join("policy.toml"))
+                .filter(|path| path.is_file())
+        });
+        if let Some(path) = user {
+            policy.restrict(Self::read(&path)?);
+        }
+        Ok(policy)
+    }
+
+    pub fn restrict(&mut self, policy: Policy) {
+        self.stricter.push(policy);
+    }
+
+    // The line given to `$SHELL -c`, split into commands as the shell will
+    // read it.
+    pub fn check_line(&self, line: &str) -> Result<Verdict, Error> {
+        match shell::commands(line) {
+            Some(words) if !words.is_empty() => self.check(&words[0], &words[1..]),
+            Some(_) => Ok(Verdict::Run),
+            None => Ok(self.unchecked()),
+        }
+    }
+
+    // For a line whose commands can't all be seen before it runs: refused
+    // with an allowlist, confirmed when anything would need a confirmation.
+    fn unchecked(&self) -> Verdict {
+        let verdicts = self.stricter.iter().map(Policy::unchecked);
+        let own = if self.allowlist {
+            Verdict::Refuse
+        } else if !self.confirm.is_empty() {
+            Verdict::Confirm
+        } else {
+            Verdict::Run
+        };
+
+        std::iter::once(own)
+            .chain(verdicts)
+            .max_by_key(|v| match v {
+                Verdict::Run => 0,
+                Verdict::Confirm => 1,
+                Verdict::Refuse => 2,
+            })
+            .unwrap()
+    }
+
+    // Every command of a pipeline or list is checked on its own.
+    pub fn check(&self, command: &str, arguments: &[String]) -> Result<Verdict, Error> {
+        let mut verdict = Verdict::Run;
+
+        for policy in &self.stricter {
+            match policy.check(command, arguments)? {
+                Verdict::Refuse => return Ok(Verdict::Refuse),
+                Verdict::Confirm => verdict = Verdict::Confirm,
+                Verdict::Run => {}
+            }
+        }
+
+        let words = std::iter::once(command.to_string())
+            .chain(arguments.iter().cloned())
+            .collect::<Vec<_>>();
+        for (command, arguments) in segments(&words) {
+            if self.allowlist && !self.any(&self.allow, command, arguments)? {
+                return Ok(Verdict::Refuse);
+            }
+            if self.any(&self.confirm, command, arguments)? {
+                verdict = Verdict::Confirm;
+            }
+        }
+        Ok(verdict)
+    }
+
+    fn any(&self, rules: &[Rule], command: &str, arguments: &[String]) -> Result<bool, Error> {
+        for rule in rules {
+            if rule.matches(command, arguments)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+// The commands of a pipeline or list with their arguments, past the
+// redirections that may come before each: `2> log rm -rf /`.
+fn segments(words: &[String]) -> Vec<(&str, &[String])> {
+    words
+        .split(|w| shell::is_separator(w))
+        .filter_map(|segment| {
+            let segment = segment.get(shell::skip_redirects(segment)..).unwrap_or(&[]);
+            segment
+                .split_first()
+                .map(|(command, arguments)| (command.as_str(), arguments))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn confirm_and_allowlist() {
+        let policy = Policy::parse(
+            Path::new("policy.toml"),
+            r#"
+            allowlist = true
+            allow = [{ command = "rm" }, { command = "ls" }, { command = "git" }]
+
+            [[confirm]]
+            command = "rm"
+            arguments = ["-*r*"]
+
+            [[confirm]]
+            command = "git"
+            arguments = ["--force", "-f"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(Verdict::Run, policy.check("rm", &args("a.txt")).unwrap());
+        assert_eq!(
+            Verdict::Confirm,
+            policy.check("/bin/rm", &args("-rf /")).unwrap()
+        );
+        assert_eq!(
+            Verdict::Confirm,
+            policy.check("ls", &args("| git push --force")).unwrap()
+        );
+        assert_eq!(Verdict::Refuse, policy.check("ls", &args("&& dd")).unwrap());
+        assert_eq!(
+            Verdict::Confirm,
+            policy.check("ls", &args("&& 2> x rm -rf /")).unwrap()
+        );
+        assert_eq!(
+            Verdict::Confirm,
+            policy.check_line("ls && > x 2>&1 rm -rf /").unwrap()
+        );
+
+        assert_eq!(Verdict::Run, policy.check_line("ls 'x;dd'").unwrap());
+        assert_eq!(Verdict::Refuse, policy.check_line("ls x;dd").unwrap());
+        assert_eq!(Verdict::Refuse, policy.check_line("ls $(dd)").unwrap());
+        assert_eq!(Verdict::Refuse, policy.check_line("ls '|' dd").unwrap());
+        assert_eq!(
+            Verdict::Run,
+            Policy::default().check_line("ls $(pwd)").unwrap()
+        );
+    }
+
+    #[test]
+    fn stricter_policy() {
+        let path = Path::new("policy.toml");
+        let mut policy =
+            Policy::parse(path, "allowlist = true\nallow = [{ command = \"ls\" }]").unwrap();
+        let user = Policy::parse(
+            path,
+            "allow = [{ command = \"dd\" }]\nconfirm = [{ command = \"ls\" }]",
+        )
+        .unwrap();
+        policy.restrict(user);
+
+        assert_eq!(Verdict::Confirm, policy.check("ls", &[]).unwrap());
+        assert_eq!(Verdict::Refuse, policy.check("dd", &[]).unwrap());
+    }
+
+    #[test]
+    fn invalid_policy() {
+        let path = Path::new("policy.toml");
+        let e = Policy::parse(path, "confirms = []").unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("policy.toml: unknown field `confirms`"));
+
+        let e = Policy::parse(path, "confirm = [{ command = \"[\" }]").unwrap_err();
+        assert!(e.to_string().starts_with("invalid pattern `[`"));
+    }
+}
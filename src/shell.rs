@@ -0,0 +1,273 @@
+use std::borrow::Cow;
+
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c)
+}
+
+// Quotes `word` for a POSIX shell, leaving it alone when nothing in it is
+// special. Single quotes are closed, escaped and reopened: `'it'\''s'`.
+pub fn quote(word: &str) -> Cow<'_, str> {
+    if !word.is_empty() && word.chars().all(is_safe) {
+        return Cow::Borrowed(word);
+    }
+
+    Cow::Owned(single_quote(word))
+}
+
+pub fn single_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// Operators that end one command of a pipeline or list and start the next.
+const SEPARATORS: &[&str] = &["|", "|&", "||", "&&", ";", "&"];
+// Operators that take the following word as a file or descriptor. Any of
+// them can start with a descriptor: `2>`.
+const REDIRECTS: &[&str] = &[
+    "<", ">", ">>", ">|", "<>", "<<", "<<<", "&>", "&>>", ">&", "<&",
+];
+
+pub fn is_separator(word: &str) -> bool {
+    SEPARATORS.contains(&word)
+}
+
+// `>`, `2>>`, and descriptor duplications such as `>&2`, `2>&1` or `<&-`.
+pub fn is_redirect(word: &str) -> bool {
+    let operator = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    if REDIRECTS.contains(&operator) {
+        return true;
+    }
+
+    match operator
+        .strip_prefix(">&")
+        .or_else(|| operator.strip_prefix("<&"))
+    {
+        Some(fd) => fd == "-" || (!fd.is_empty() && fd.chars().all(|c| c.is_ascii_digit())),
+        None => false,
+    }
+}
+
+// The words before the command of a segment that are redirections and
+// their files: 2 in `< input sort`, 1 in `2>&1 make`.
+pub fn skip_redirects<S: AsRef<str>>(words: &[S]) -> usize {
+    let mut i = 0;
+    while let Some(word) = words.get(i).map(AsRef::as_ref).filter(|w| is_redirect(w)) {
+        let operator = word.trim_start_matches(|c: char| c.is_ascii_digit());
+        i += if REDIRECTS.contains(&operator) { 2 } else { 1 };
+    }
+    i
+}
+
+// A word of a line given to `$SHELL -c`. Words are left as they were typed,
+// so that globs, variables, `~` and redirections work; `literal` ones (typed
+// quoted, completed or recalled) and words with blanks are quoted.
+pub fn word(word: &str, literal: bool) -> Cow<'_, str> {
+    if literal || word.chars().any(char::is_whitespace) {
+        return quote(word);
+    }
+
+    Cow::Borrowed(word)
+}
+
+// Splits a line into words the way a POSIX shell would, without any
+// expansion. Returns `None` when a quote is left open.
+pub fn split(line: &str) -> Option<Vec<String>> {
+    split_quoted(line).map(|words| words.into_iter().map(|(word, _)| word).collect())
+}
+
+// `split`, with whether anything in each word was quoted or escaped.
+pub fn split_quoted(line: &str) -> Option<Vec<(String, bool)>> {
+    tokens(line).map(|(words, _)| words)
+}
+
+// `split`, or `None` as well when a command is hidden in `$(...)`,
+// backquotes or parentheses, where it is only known by running the shell.
+pub fn commands(line: &str) -> Option<Vec<String>> {
+    tokens(line)
+        .filter(|(_, hidden)| !hidden)
+        .map(|(words, _)| words.into_iter().map(|(word, _)| word).collect())
+}
+
+fn tokens(line: &str) -> Option<(Vec<(String, bool)>, bool)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut hidden = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                quoted = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                quoted = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => {
+                            let c = chars.next()?;
+                            if !"$`\"\\\n".contains(c) {
+                                word.push('\\');
+                            }
+                            word.push(c);
+                        }
+                        c => {
+                            hidden |= c == '`' || (c == '$' && chars.peek() == Some(&'('));
+                            word.push(c)
+                        }
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                quoted = true;
+                word.extend(chars.next());
+            }
+            '\n' | ';' | '|' | '&' | '<' | '>' => {
+                // Digits right before a redirection are its descriptor.
+                let descriptor = in_word
+                    && !quoted
+                    && "<>".contains(c)
+                    && word.chars().all(|c| c.is_ascii_digit());
+                if in_word && !descriptor {
+                    words.push((std::mem::take(&mut word), quoted));
+                }
+                in_word = false;
+                quoted = false;
+
+                let mut operator = std::mem::take(&mut word);
+                operator.push(if c == '\n' { ';' } else { c });
+                while let Some(&next) = chars.peek() {
+                    let mut longer = operator.clone();
+                    longer.push(next);
+                    if !is_separator(&longer) && !is_redirect(&longer) {
+                        break;
+                    }
+                    operator = longer;
+                    chars.next();
+                }
+                words.push((operator, false));
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push((std::mem::take(&mut word), quoted));
+                    in_word = false;
+                    quoted = false;
+                }
+            }
+            c => {
+                hidden |= c == '`' || c == '(' || c == ')';
+                hidden |= c == '$' && chars.peek() == Some(&'(');
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push((word, quoted));
+    }
+
+    Some((words, hidden))
+}
+
+pub fn line<S: AsRef<str>>(command: &str, arguments: &[(S, bool)]) -> String {
+    std::iter::once(self::word(command, false))
+        .chain(
+            arguments
+                .iter()
+                .map(|(a, literal)| self::word(a.as_ref(), *literal)),
+        )
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Key bindings printed by `man-with init <SHELL>`.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+pub fn init(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(include_str!("../shell/man-with.bash")),
+        "zsh" => Some(include_str!("../shell/man-with.zsh")),
+        "fish" => Some(include_str!("../shell/man-with.fish")),
+        _ => None,
+    }
+}
+
+pub fn join<S: AsRef<str>>(command: &str, arguments: &[S]) -> String {
+    std::iter::once(quote(command))
+        .chain(arguments.iter().map(|a| quote(a.as_ref())))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!("--color=auto", quote("--color=auto"));
+        assert_eq!("''", quote(""));
+        assert_eq!("'a b'", quote("a b"));
+        assert_eq!("'it'\\''s'", quote("it's"));
+        assert_eq!("'~/src'", quote("~/src"));
+        assert_eq!(
+            "git commit -m 'fix $HOME'",
+            join("git", &["commit", "-m", "fix $HOME"])
+        );
+    }
+
+    #[test]
+    fn shell_line() {
+        let typed = "make ~/src *.rs $HOME $(id) 2>&1 | less 'a|b' 'x;y' '$(x)' 'a b'";
+        let words = split_quoted(typed).unwrap();
+        assert_eq!(typed, line(&words[0].0, &words[1..]));
+        assert_eq!(
+            "ls '*.rs' 'a b'",
+            line("ls", &[("*.rs", true), ("a b", false)])
+        );
+
+        assert!(is_separator("&&") && !is_separator(">"));
+        for redirect in &["2>", "2>>", ">&2", "2>&1", "<&-", "10>"] {
+            assert!(is_redirect(redirect), "{}", redirect);
+        }
+        assert!(!is_redirect("-l") && !is_redirect("2") && !is_redirect(">&x"));
+        assert_eq!(1, skip_redirects(&["2>&1", "make"]));
+        assert_eq!(4, skip_redirects(&["<", "in", "2>", "err", "sort"]));
+    }
+
+    #[test]
+    fn splitting() {
+        let words = vec!["git", "commit", "-m", "it's $HOME", "", "a\\b"];
+        assert_eq!(words, split(&join(words[0], &words[1..])).unwrap());
+        assert_eq!(
+            vec!["a b", "c\"d", "e\\f", "g"],
+            split(r#"  a\ b "c\"d" "e\f"  g"#).unwrap()
+        );
+        assert_eq!(None, split("echo 'open"));
+        assert_eq!(
+            vec!["ls", "x", ";", "dd", "2>&1", "|", "a|b", ">&2", ";", "b"],
+            split("ls x;dd 2>&1|'a|b'>&2\nb").unwrap()
+        );
+        assert_eq!(None, commands("ls \"$(dd)\""));
+        assert_eq!(None, commands("ls `dd`"));
+        assert!(commands("ls '$(dd)'").is_some());
+    }
+
+    #[test]
+    fn init_scripts() {
+        for shell in SHELLS {
+            assert!(init(shell).unwrap().contains("man-with --print"));
+        }
+        assert_eq!(None, init("tcsh"));
+    }
+}
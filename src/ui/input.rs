@@ -6,42 +6,36 @@ use termion::event::Key;
 use termion::input::TermRead;
 
 use crate::event::Event;
+use crate::keymap::{Action, Keymap, Lookup};
 
 pub struct Input {}
 
 impl Input {
-    pub fn reader(tx: Sender<Event>) -> Result<(), Error> {
+    pub fn reader(tx: Sender<Event>, keymap: Keymap) -> Result<(), Error> {
         let stdin = stdin();
+        let mut pending = vec![];
 
         for c in stdin.keys() {
-            let _ = match c? {
-                Key::Ctrl('a') => tx.send(Event::MoveTo(0))?,
-                Key::Ctrl('e') => tx.send(Event::MoveTo(-1))?,
-                //Key::Ctrl('c') => tx.send(Event::Candidate(super::prompt::PromptMode::Choose))?,
-                Key::Ctrl('r') => tx.send(Event::History)?,
-                Key::Ctrl('b') => tx.send(Event::Back)?,
-                Key::Ctrl('f') => tx.send(Event::Forward)?,
-                Key::Ctrl('p') => tx.send(Event::Prev)?,
-                Key::Ctrl('n') => tx.send(Event::Next)?,
-                Key::Ctrl('d') => tx.send(Event::Delete)?,
-                Key::Char('\n') => tx.send(Event::Enter)?,
-                Key::Char('\t') => tx.send(Event::Tab)?,
-                Key::Char(c) => tx.send(Event::Key(c))?,
-                Key::Esc | Key::Ctrl('c') => {
-                    tx.send(Event::Quit)?;
-                    break;
+            pending.push(c?);
+
+            match keymap.lookup(&pending) {
+                Lookup::Action(action) => {
+                    pending.clear();
+                    tx.send(Event::Action(action))?;
+                    if action == Action::Quit {
+                        break;
+                    }
+                }
+                Lookup::Prefix => {}
+                Lookup::None => {
+                    // Unbound characters are typed, other unbound keys and
+                    // sequences are dropped.
+                    if let [Key::Char(c)] = pending[..] {
+                        tx.send(Event::Key(c))?;
+                    }
+                    pending.clear();
                 }
-                Key::Backspace => tx.send(Event::Backspace)?,
-                Key::Delete => tx.send(Event::Delete)?,
-                Key::Left => tx.send(Event::Left)?,
-                Key::Right => tx.send(Event::Right)?,
-                Key::Down => tx.send(Event::Down)?,
-                Key::Up => tx.send(Event::Up)?,
-                Key::F(1) => tx.send(Event::Fn1)?,
-                Key::F(2) => tx.send(Event::Fn2)?,
-                Key::F(3) => tx.send(Event::Fn3)?,
-                _ => {}
-            };
+            }
         }
 
         Ok(())
@@ -6,4 +6,3 @@ mod input;
 
 pub use self::input::Input;
 pub use self::prompt::Prompt;
-pub use self::viewer::Viewer;
@@ -1,76 +1,116 @@
-use serde_derive::{Deserialize, Serialize};
-use std::io::{BufRead, Write};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::Write;
 use std::path::PathBuf;
-
-use super::viewer::{SourceType, Viewer};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use super::viewer::{self, SourceType, Viewer};
+use crate::config::Config;
+use crate::event::Event;
+use crate::fuzzy;
+use crate::help::{Help, Subcommand};
+use crate::history;
+use crate::man::{self, overstrike, Line, Span};
+use crate::options::{split_value, Opt, Options};
+use crate::shell;
 use crate::ui::cursor;
-use terminal_size::terminal_size;
+use terminal_size::{terminal_size, Width};
 use termion;
+use unicode_width::UnicodeWidthStr;
 
-const PROMPT: &'static str = "> ";
+const CHUNK: usize = 256;
 
 #[derive(Clone, PartialEq)]
 pub enum PromptMode {
     Prompt,
-    History,
     File,
     Choose,
 }
 
-#[derive(Serialize, Deserialize)]
-struct History {
-    command: String,
-    argument: Vec<String>,
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Page,
+    File,
+    History,
 }
 
-impl History {
-    fn write(&self, history: &PathBuf) {
-        let json = serde_json::to_string(self).unwrap();
-
-        std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(history)
-            .and_then(|mut f| {
-                f.write_all(json.as_bytes())
-                    .and_then(move |_| f.write(b"\n"))
-            })
-            .unwrap();
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub text: String,
+    pub description: Option<String>,
+    pub source: Source,
+}
+
+impl Candidate {
+    fn new(text: String, source: Source) -> Self {
+        Candidate {
+            text,
+            description: None,
+            source,
+        }
     }
 
-    fn read(command: &String, history: &PathBuf) -> Vec<Vec<String>> {
-        if !std::path::Path::new(history).exists() {
-            return Vec::default();
+    fn option(text: String, opt: &Opt) -> Self {
+        // The first sentence is enough for a one line hint.
+        let summary = opt
+            .description
+            .split(". ")
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        Candidate {
+            text,
+            description: Some(summary).filter(|s| !s.is_empty()),
+            source: Source::Page,
         }
+    }
+}
 
-        let f = std::fs::File::open(history);
-        let mut arguments: Vec<Vec<String>> = Vec::default();
+#[derive(Clone)]
+struct Menu {
+    candidates: Vec<Candidate>,
+    selected: usize,
+}
 
-        if let Ok(file) = f {
-            let lines = std::io::BufReader::new(file).lines();
-            for line in lines {
-                let l = line.unwrap();
-                let hist: Result<History, _> = serde_json::from_str(l.as_str());
+impl Menu {
+    fn next(&mut self) {
+        self.selected = (self.selected + 1) % self.candidates.len();
+    }
 
-                match hist {
-                    Ok(mut h) => {
-                        if *command == h.command {
-                            h.argument.push(String::default());
-                            arguments.push(h.argument.clone())
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
+    fn prev(&mut self) {
+        self.selected = (self.selected + self.candidates.len() - 1) % self.candidates.len();
+    }
 
-        arguments
+    fn current(&self) -> &Candidate {
+        &self.candidates[self.selected]
     }
 }
 
+// A reverse incremental search through the history, opened with C-r.
+#[derive(Clone, Default)]
+struct Search {
+    query: String,
+    // Indexes of the matching entries of `histories`, best first.
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+const SEARCH: &str = "(history) ";
+
+fn history_line(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .filter(|a| !a.is_empty())
+        .map(|a| shell::quote(a))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[derive(Clone)]
-pub struct Prompt<T: Write + Send + Drop> {
-    panel: Vec<String>,
+pub struct Prompt<T: Write + Send> {
     pub command: String,
     pub stdout: T,
     pub cursor: usize,
@@ -78,17 +118,47 @@ pub struct Prompt<T: Write + Send + Drop> {
     pub mode: PromptMode,
     pub argument: Vec<String>,
     pub completion: Option<String>,
+    menu: Option<Menu>,
+    // The active key bindings, shown over the panel.
+    bindings: Option<Vec<String>>,
     buffer: Vec<String>,
+    styles: Vec<Vec<Span>>,
+    options: Options,
+    subcommands: Vec<Subcommand>,
+    // Whether a `command-arg` man page exists, looked up once per pair
+    // since the lookup scans the man directories.
+    subcommand_pages: HashMap<(String, String), bool>,
     pos: usize,
     size: usize,
     selected: usize,
-    history_index: u64,
+    search: Option<Search>,
     history_path: Option<PathBuf>,
     histories: Vec<Vec<String>>,
     choose_pos: usize,
     save_argument: Option<Vec<String>>,
 
     file_input: String,
+    files: Vec<String>,
+    // The scores of the paths listed in `buffer`, best first.
+    file_ranking: Vec<i64>,
+
+    loader: Option<Sender<Event>>,
+    generation: usize,
+    loading: bool,
+    quitted: bool,
+
+    // An open quote or a pending backslash in the argument being typed.
+    quote: Option<char>,
+    escape: bool,
+    shell: bool,
+    // Arguments the shell must take as they are: typed with quotes or a
+    // backslash, completed or recalled. The others are run as typed.
+    literal: Vec<bool>,
+    config: Config,
+}
+
+fn split_lines(lines: Vec<Line>) -> (Vec<String>, Vec<Vec<Span>>) {
+    lines.into_iter().map(|l| (l.text, l.spans)).unzip()
 }
 
 fn is_args(ch: char) -> bool {
@@ -98,7 +168,7 @@ fn is_args(ch: char) -> bool {
     }
 }
 
-impl<T: Write + Send + Drop> Prompt<T> {
+impl<T: Write + Send> Prompt<T> {
     pub fn new(
         stdout: T,
         command: &str,
@@ -113,30 +183,41 @@ impl<T: Write + Send + Drop> Prompt<T> {
             _ => Viewer::new(command, SourceType::Man),
         };
 
-        let buffer = { viewer.source() };
-
         Prompt {
-            panel: vec![String::new(); height],
             command: String::from(command),
             argument: vec![String::default()],
             stdout,
             completion: None,
-            viewer: viewer,
-            buffer: buffer
-                .split('\n')
-                .map(ToString::to_string)
-                .collect::<Vec<String>>(),
+            menu: None,
+            bindings: None,
+            viewer,
+            options: Options::default(),
+            subcommands: Vec::default(),
+            subcommand_pages: HashMap::default(),
+            buffer: Vec::default(),
+            styles: Vec::default(),
             cursor: 0,
             pos: 0,
             size: height,
             selected: 0,
-            history_index: 0u64,
+            search: None,
             history_path,
             histories: Vec::default(),
             mode: PromptMode::Prompt,
             choose_pos: 0,
             save_argument: None,
             file_input: String::default(),
+            files: Vec::default(),
+            file_ranking: Vec::default(),
+            loader: None,
+            generation: 0,
+            loading: false,
+            quitted: false,
+            quote: None,
+            escape: false,
+            shell: false,
+            literal: Vec::default(),
+            config: Config::default(),
         }
     }
 
@@ -145,49 +226,190 @@ impl<T: Write + Send + Drop> Prompt<T> {
 
         match self.mode {
             PromptMode::File => {
-                self.buffer = self
-                    .viewer
-                    .file_path(None)
-                    .split('\n')
-                    .map(ToString::to_string)
-                    .collect::<Vec<String>>()
-            }
-            PromptMode::Prompt => {
-                self.buffer = self
-                    .viewer
-                    .source()
-                    .split('\n')
-                    .map(ToString::to_string)
-                    .collect::<Vec<String>>()
+                self.files.clear();
+                self.file_input.clear();
+                self.filter_files();
+                self.load_files();
             }
+            PromptMode::Prompt => self.load(),
             PromptMode::Choose => {
+                self.generation += 1;
+                self.loading = false;
                 self.buffer = vec!["man".to_owned(), "file".to_owned()];
+                self.styles.clear();
             }
-            _ => {}
         }
     }
 
-    pub fn get_mode<'a>(&'a self) -> &'a PromptMode {
-        &self.mode
+    // Sources are loaded on a background thread when a loader is set, so
+    // the prompt can be used while a large page is still being rendered.
+    pub fn set_loader(&mut self, loader: Sender<Event>) {
+        self.loader = Some(loader);
     }
 
-    pub fn quit(&self) {}
+    pub fn load(&mut self) {
+        self.generation += 1;
+        self.buffer.clear();
+        self.styles.clear();
+        self.pos = 0;
+
+        let loader = match self.loader.clone() {
+            Some(loader) if !self.viewer.is_stdin() => loader,
+            _ => {
+                let (lines, help) = self.viewer.load();
+                let generation = self.generation;
+                self.receive(generation, lines);
+                return self.loaded(generation, help);
+            }
+        };
+
+        self.loading = true;
+        let viewer = self.viewer.clone();
+        let generation = self.generation;
+        thread::spawn(move || {
+            let (lines, help) = viewer.load();
+            for chunk in lines.chunks(CHUNK) {
+                if loader
+                    .send(Event::Source(generation, chunk.to_vec()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            let _ = loader.send(Event::Loaded(generation, help));
+        });
+    }
+
+    fn load_files(&mut self) {
+        self.generation += 1;
+
+        let loader = match self.loader.clone() {
+            Some(loader) => loader,
+            None => {
+                self.files = self.viewer.files(None);
+                return self.filter_files();
+            }
+        };
+
+        self.loading = true;
+        let viewer = self.viewer.clone();
+        let generation = self.generation;
+        thread::spawn(move || {
+            let mut chunk = Vec::with_capacity(CHUNK);
+            viewer.walk_files(None, |path| {
+                chunk.push(overstrike::decode(&path));
+                if chunk.len() < CHUNK {
+                    return true;
+                }
+                let lines = std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK));
+                loader.send(Event::Source(generation, lines)).is_ok()
+            });
+            let _ = loader
+                .send(Event::Source(generation, chunk))
+                .and_then(|_| loader.send(Event::Loaded(generation, Help::default())));
+        });
+    }
+
+    // Lines from a load that has since been superseded are dropped.
+    pub fn receive(&mut self, generation: usize, lines: Vec<Line>) {
+        if generation != self.generation {
+            return;
+        }
+
+        if *self.get_mode() == PromptMode::File {
+            // Only the new paths are ranked, then merged into the list.
+            let paths = lines.into_iter().map(|l| l.text).collect::<Vec<_>>();
+            let more = fuzzy::rank_scored(&self.file_input, paths.clone(), |p| p.as_str());
+            self.files.extend(paths);
 
-    pub fn write_history(&self) {
-        if let Some(history) = &self.history_path {
-            let args = self
-                .argument
-                .clone()
+            let ranked = std::mem::take(&mut self.file_ranking)
                 .into_iter()
-                .filter(|s| !s.is_empty())
+                .zip(self.buffer.drain(..))
                 .collect::<Vec<_>>();
-            if !args.is_empty() {
-                let hist = History {
-                    command: self.command.clone(),
-                    argument: args.clone(),
-                };
-                hist.write(history);
+            let merged = fuzzy::merge(ranked, more, |p| p.as_str());
+            let (scores, paths): (Vec<_>, Vec<_>) = merged.into_iter().unzip();
+            self.file_ranking = scores;
+            self.buffer = paths;
+            if self.buffer.is_empty() {
+                self.buffer.push(String::default());
             }
+            self.pos = self.pos.min(self.buffer.len() - 1);
+        } else {
+            let (buffer, styles) = split_lines(lines);
+            self.buffer.extend(buffer);
+            self.styles.extend(styles);
+        }
+    }
+
+    pub fn loaded(&mut self, generation: usize, help: Help) {
+        if generation != self.generation {
+            return;
+        }
+        self.loading = false;
+        if *self.get_mode() == PromptMode::File {
+            return;
+        }
+        if self.buffer.is_empty() {
+            self.buffer.push(String::default());
+        }
+
+        self.options = help.options;
+        // Keep the parent's list while a subcommand's page is shown. Man
+        // pages are left to the `git-*` naming convention, their prose
+        // looks too much like a listing.
+        if self.viewer.subcommand().is_none() && self.viewer.is_help() {
+            self.subcommands = help.subcommands;
+        }
+
+        // Arguments typed (or preloaded) while the parent page was loading
+        // may name a subcommand, which is then loaded instead.
+        let generation = self.generation;
+        self.sync_subcommand();
+        if generation != self.generation {
+            return;
+        }
+
+        let position = if self.argument[self.selected].is_empty() {
+            self.last_option_position()
+        } else {
+            self.find_position()
+        };
+        if let Some(n) = position {
+            self.pos = n;
+        }
+    }
+
+    // Starts with `arguments` already entered and the cursor on a new one
+    // after them, as in `man-with tar -x -f`. Each comes with whether it was
+    // quoted, see `literal`.
+    pub fn preload(&mut self, arguments: Vec<(String, bool)>) {
+        if arguments.is_empty() {
+            return;
+        }
+
+        self.reset_quote();
+        let (argument, literal) = arguments.into_iter().unzip();
+        self.argument = argument;
+        self.literal = literal;
+        self.argument.push(String::default());
+        self.selected = self.argument.len() - 1;
+        self.cursor = 0;
+    }
+
+    pub fn get_mode(&self) -> &PromptMode {
+        &self.mode
+    }
+
+    pub fn quit(&mut self) {
+        self.quitted = true;
+    }
+
+    // The line to run, or `None` when the prompt was quit instead.
+    pub fn accepted_command(&self) -> Option<(String, Vec<String>)> {
+        if self.quitted {
+            None
+        } else {
+            Some(self.full_command())
         }
     }
 
@@ -203,10 +425,41 @@ impl<T: Write + Send + Drop> Prompt<T> {
     }
 
     pub fn read_history(&mut self) {
-        if let Some(history) = &self.history_path {
-            let histories = History::read(&self.command, history);
-            self.histories = histories;
+        if let Some(path) = &self.history_path {
+            let entries = history::read(path).unwrap_or_default();
+            let cwd = env::current_dir().unwrap_or_default();
+            self.histories = history::recall(
+                &entries,
+                &self.command,
+                &cwd,
+                history::now(),
+                self.config.history_failed,
+            );
+        }
+    }
+
+    // The accepted line for `$SHELL -c`.
+    pub fn shell_line(&self) -> String {
+        let arguments = self
+            .argument
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| !a.is_empty())
+            .map(|(i, a)| (a.as_str(), self.is_literal(i)))
+            .collect::<Vec<_>>();
+
+        shell::line(&self.command, &arguments)
+    }
+
+    fn is_literal(&self, i: usize) -> bool {
+        self.literal.get(i).copied().unwrap_or(false)
+    }
+
+    fn set_literal(&mut self) {
+        if self.literal.len() <= self.selected {
+            self.literal.resize(self.selected + 1, false);
         }
+        self.literal[self.selected] = true;
     }
 
     pub fn full_command(&self) -> (String, Vec<String>) {
@@ -220,12 +473,104 @@ impl<T: Write + Send + Drop> Prompt<T> {
         (self.command.clone(), a.clone())
     }
 
-    #[allow(dead_code)]
-    pub fn change_command(&mut self, command: String) {
-        self.command = command
+    // Switches the documentation to `command` or one of its subcommands.
+    // The command that gets executed is left alone.
+    pub fn change_command(&mut self, command: &str, subcommand: Option<String>) {
+        self.viewer.set_command(command, subcommand);
+        self.load();
+    }
+
+    // A subcommand is known from the parent page's listing (which also
+    // resolves aliases such as `cargo b`) or from `git-*` style man pages.
+    fn resolve_subcommand(&mut self, command: &str, arg: &str) -> Option<String> {
+        if arg.is_empty() || arg.starts_with('-') {
+            return None;
+        }
+
+        let listed = self
+            .subcommands
+            .iter()
+            .find(|s| s.name == arg || s.aliases.iter().any(|a| a == arg))
+            .map(|s| s.name.clone());
+        if listed.is_some() {
+            return listed;
+        }
+
+        let page = *self
+            .subcommand_pages
+            .entry((command.to_string(), arg.to_string()))
+            .or_insert_with(|| man::find(&format!("{}-{}", command, arg)).is_some());
+        Some(arg.to_string()).filter(|_| page)
+    }
+
+    // When the line is run by the shell, `|`, `&&` and the like start
+    // another command. Returns the command of the segment being edited and
+    // the index of its first argument, or `None` while the command itself
+    // is still being typed.
+    fn segment(&self) -> Option<(String, usize)> {
+        let start = match self.argument[..self.selected]
+            .iter()
+            .rposition(|a| shell::is_separator(a))
+        {
+            Some(i) if self.shell => i + 1,
+            _ => return Some((self.command.clone(), 0)),
+        };
+
+        // Redirections may come before the command: `< input sort`.
+        let i = start + shell::skip_redirects(&self.argument[start..self.selected]);
+        if i >= self.selected {
+            return None;
+        }
+
+        Some((self.argument[i].clone(), i + 1))
+    }
+
+    // Shows the subcommand's page once the first argument is complete and
+    // goes back to the parent page when it is edited away.
+    pub fn sync_subcommand(&mut self) {
+        if self.viewer.is_stdin() {
+            return;
+        }
+
+        let (command, start) = match self.segment() {
+            Some(segment) => segment,
+            None => return,
+        };
+        // The subcommand is looked for once the command's page is loaded.
+        if command != self.viewer.command() {
+            return self.change_command(&command, None);
+        }
+
+        let current = self.viewer.subcommand().map(ToString::to_string);
+        let wanted = if self.selected > start || current.is_some() {
+            let arg = self.argument[start].clone();
+            self.resolve_subcommand(&command, &arg)
+        } else {
+            None
+        };
+
+        if wanted != current {
+            self.change_command(&command, wanted);
+        }
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    pub fn set_shell(&mut self, shell: bool) {
+        self.shell = shell;
     }
 
     pub fn up(&mut self) {
+        if let Some(menu) = &mut self.menu {
+            menu.prev();
+            return;
+        }
+        if self.search.is_some() {
+            return self.search_forward();
+        }
+
         let pos = self.pos as i64;
 
         if (pos - 1) < 0 {
@@ -236,6 +581,14 @@ impl<T: Write + Send + Drop> Prompt<T> {
     }
 
     pub fn down(&mut self) {
+        if let Some(menu) = &mut self.menu {
+            menu.next();
+            return;
+        }
+        if self.search.is_some() {
+            return self.search_backward();
+        }
+
         if (self.pos + 1) > self.buffer.len() {
             self.pos = self.buffer.len();
         } else {
@@ -250,12 +603,17 @@ impl<T: Write + Send + Drop> Prompt<T> {
                     self.choose_pos += 1;
                 }
             }
+            PromptMode::File => {
+                if self.buffer.len() > self.pos + 1 {
+                    self.pos += 1;
+                }
+            }
             _ => {
-                let s = self.pos + 1;
-                let b = &self.buffer[s..self.buffer.len()];
+                let mentions = self.mentions();
+                let next = mentions.iter().find(|n| **n > self.pos);
 
-                if let Some(n) = self.find_position(&b.to_vec()) {
-                    self.pos = s + n;
+                if let Some(n) = next.or_else(|| mentions.first()) {
+                    self.pos = *n;
                 }
             }
         }
@@ -270,45 +628,58 @@ impl<T: Write + Send + Drop> Prompt<T> {
                     self.choose_pos -= 1
                 }
             }
+            PromptMode::File => {
+                self.pos = self.pos.saturating_sub(1);
+            }
             _ => {
-                let e = self.pos - 1;
-                let mut b = self.buffer[0..e].to_vec();
-                b.reverse();
-                if let Some(n) = self.find_position(&b) {
-                    self.pos = e - n - 1;
+                let mentions = self.mentions();
+                let prev = mentions.iter().rev().find(|n| **n < self.pos);
+
+                if let Some(n) = prev.or_else(|| mentions.last()) {
+                    self.pos = *n;
                 }
             }
         }
     }
 
     pub fn select_back(&mut self) {
+        self.menu = None;
+        self.reset_quote();
         if self.selected > 0 {
             self.selected -= 1;
             self.cursor = 0;
             self.completion = None;
         }
+        self.sync_subcommand();
     }
 
     pub fn select_forward(&mut self) {
+        self.menu = None;
+        self.reset_quote();
         if self.selected < (self.argument.len() - 1) {
             self.selected += 1;
             self.cursor = 0;
             self.completion = None;
         }
+        self.sync_subcommand();
     }
 
     pub fn end_of_line(&mut self) {
+        self.reset_quote();
         if !self.argument.is_empty() {
             self.selected = self.argument.len() - 1;
             self.cursor = self.argument[self.selected].len();
         }
+        self.sync_subcommand();
     }
 
     pub fn beginning_of_line(&mut self) {
+        self.reset_quote();
         if !self.argument.is_empty() {
             self.selected = 0;
             self.cursor = 1;
         }
+        self.sync_subcommand();
     }
 
     pub fn cursor_forward(&mut self) {
@@ -341,26 +712,53 @@ impl<T: Write + Send + Drop> Prompt<T> {
     }
 
     pub fn backspace(&mut self) {
+        self.menu = None;
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            return self.filter_history();
+        }
+        if *self.get_mode() == PromptMode::File {
+            if let Some(ch) = self.file_input.pop() {
+                self.cursor = self.cursor.saturating_sub(ch.len_utf8());
+                self.filter_files();
+            }
+            return;
+        }
+
+        // Take back a quote or backslash that nothing has followed yet.
+        if self.escape {
+            self.escape = false;
+            return;
+        }
+        if self.quote.is_some() && self.argument[self.selected].is_empty() {
+            self.quote = None;
+            return;
+        }
+
         let input = &mut self.argument[self.selected];
 
-        if let Some(ch) = input[0..self.cursor].chars().rev().next() {
+        if let Some(ch) = input[0..self.cursor].chars().next_back() {
             self.cursor -= ch.len_utf8();
             input.remove(self.cursor);
 
-            match self.get_mode() {
-                PromptMode::Prompt => {
-                    if let Some(n) = self.find_position(&self.buffer) {
-                        self.pos = n;
-                    }
-                },
-                _ => {}
+            if *self.get_mode() == PromptMode::Prompt {
+                if let Some(n) = self.find_position() {
+                    self.pos = n;
+                }
+            }
+        } else if input.is_empty() && self.selected > 0 {
+            if self.is_last() {
+                self.argument.pop();
+                self.literal.truncate(self.argument.len());
             }
-        } else if input.is_empty() {
             self.selected -= 1;
+            self.cursor = self.argument[self.selected].len();
         }
+        self.sync_subcommand();
     }
 
     pub fn delete(&mut self) {
+        self.menu = None;
         let input = &mut self.argument[self.selected];
 
         if self.cursor == 0 || input[0..self.cursor].chars().next().is_some() {
@@ -368,10 +766,11 @@ impl<T: Write + Send + Drop> Prompt<T> {
                 input.remove(self.cursor);
             }
 
-            if let Some(n) = self.find_position(&self.buffer) {
+            if let Some(n) = self.find_position() {
                 self.pos = n;
             }
         }
+        self.sync_subcommand();
     }
 
     pub fn save_cache(&mut self) {
@@ -385,40 +784,94 @@ impl<T: Write + Send + Drop> Prompt<T> {
     }
 
     pub fn append(&mut self) {
+        self.menu = None;
+        self.reset_quote();
         if self.is_last() {
             self.argument.push(String::default());
+            self.literal.truncate(self.argument.len() - 1);
         }
 
         self.selected += 1;
         self.cursor = 0;
+        self.sync_subcommand();
+    }
+
+    // Quotes and backslashes work like in a shell: they are not part of the
+    // argument, and a space inside them doesn't start the next one.
+    pub fn key(&mut self, ch: char) {
+        if let Some(search) = &mut self.search {
+            search.query.push(ch);
+            return self.filter_history();
+        }
+        if *self.get_mode() != PromptMode::Prompt {
+            return match ch {
+                ' ' => self.append(),
+                _ => self.insert(ch),
+            };
+        }
+        if self.escape {
+            self.escape = false;
+            return self.insert(ch);
+        }
+
+        match (self.quote, ch) {
+            (Some(q), c) if c == q => self.quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                self.escape = true;
+                self.set_literal();
+            }
+            (Some(_), c) => self.insert(c),
+            (None, '\'') | (None, '"') => {
+                self.quote = Some(ch);
+                self.set_literal();
+            }
+            (None, ' ') => self.append(),
+            (None, c) => self.insert(c),
+        }
+    }
+
+    fn reset_quote(&mut self) {
+        self.quote = None;
+        self.escape = false;
     }
 
     pub fn insert(&mut self, ch: char) {
+        self.menu = None;
         match self.get_mode() {
             PromptMode::Prompt => {
                 let input = &mut self.argument[self.selected];
                 input.insert(self.cursor, ch);
                 self.cursor += ch.len_utf8();
 
-                if let Some(n) = self.find_position(&self.buffer) {
+                if let Some(n) = self.find_position() {
                     self.pos = n;
                 }
-            },
+            }
             _ => {
-                let input = &mut self.file_input;
-                input.insert(self.cursor, ch);
+                self.file_input.push(ch);
                 self.cursor += ch.len_utf8();
-                /*
-                if let Some(n) = self.find_position(&self.buffer) {
-                    self.pos = n;
-                }
-                */
+                self.filter_files();
             }
         }
     }
 
+    // File mode lists the paths matching what has been typed, best first.
+    fn filter_files(&mut self) {
+        let ranked = fuzzy::rank_scored(&self.file_input, self.files.clone(), |p| p.as_str());
+        let (scores, paths) = ranked.into_iter().unzip();
+        self.file_ranking = scores;
+        self.buffer = paths;
+        self.styles.clear();
+        if self.buffer.is_empty() {
+            self.buffer.push(String::default());
+        }
+        self.pos = 0;
+    }
+
     pub fn insert_line(&mut self, line: String) {
-        self.buffer.push(line)
+        let line = overstrike::decode(&line);
+        self.buffer.push(line.text);
+        self.styles.push(line.spans);
     }
 
     pub fn append_argument(&mut self, s: String) {
@@ -426,21 +879,41 @@ impl<T: Write + Send + Drop> Prompt<T> {
         input.push_str(&s);
     }
 
-    fn candidates(&self) -> Vec<String> {
+    fn candidates(&self) -> Vec<Candidate> {
         if self.cursor == 0 {
             return Vec::default();
         }
 
         let n = &self.argument[self.selected];
-        let hits = self
+        let mut hits = if n.starts_with('-') {
+            self.option_candidates(n)
+        } else {
+            self.word_candidates(n)
+        };
+
+        for candidate in self.history_candidates(n) {
+            if !hits.iter().any(|h| h.text == candidate.text) {
+                hits.push(candidate);
+            }
+        }
+
+        hits
+    }
+
+    fn word_candidates(&self, n: &str) -> Vec<Candidate> {
+        let mut seen = HashSet::new();
+        let tokens = self
             .buffer
             .iter()
-            .filter(|line| line.contains(n))
-            .map(|line| line.split_whitespace().filter(|tok| tok.contains(n)))
-            .flatten()
-            .map(|token| token.matches(is_args).collect());
+            .flat_map(|line| line.split_whitespace())
+            .map(|token| token.matches(is_args).collect::<String>())
+            .filter(|token| !token.is_empty() && seen.insert(token.clone()))
+            .collect::<Vec<_>>();
 
-        let mut hits = hits.collect::<Vec<String>>();
+        let mut hits = fuzzy::rank(n, tokens, |t| t.as_str())
+            .into_iter()
+            .map(|t| Candidate::new(t, Source::Page))
+            .collect::<Vec<_>>();
 
         if n.starts_with(".") || n.starts_with("~") {
             // File path candidates
@@ -460,33 +933,237 @@ impl<T: Write + Send + Drop> Prompt<T> {
             };
 
             let paths = std::fs::read_dir(dir)
-                .and_then(|p| Ok(p.into_iter().flatten().collect::<Vec<_>>()))
-                .and_then(|paths| {
-                    let dirs = paths
+                .map(|p| p.flatten().collect::<Vec<_>>())
+                .map(|paths| {
+                    paths
                         .iter()
                         .map(|p| {
                             p.path()
                                 .file_name()
                                 .map(|path| input_path.join(path).to_string_lossy().to_string())
                         })
-                        .collect::<Vec<_>>();
-                    Ok(dirs)
+                        .collect::<Vec<_>>()
                 });
 
             if let Ok(ps) = paths {
-                hits.append(
-                    &mut ps
-                        .into_iter()
-                        .flatten()
-                        .filter(|p| p.starts_with(n))
-                        .collect::<Vec<_>>(),
-                )
+                let ps = ps.into_iter().flatten().collect::<Vec<_>>();
+                let mut files = fuzzy::rank(n, ps, |p| p.as_str())
+                    .into_iter()
+                    .map(|p| Candidate::new(p, Source::File))
+                    .collect::<Vec<_>>();
+
+                // Paths are what was asked for, so they come first.
+                hits.retain(|h| !files.iter().any(|f| f.text == h.text));
+                files.append(&mut hits);
+                hits = files;
             }
         }
 
         hits
     }
 
+    // Arguments used with this command before.
+    fn history_candidates(&self, n: &str) -> Vec<Candidate> {
+        let mut seen = HashSet::new();
+        let tokens = self
+            .histories
+            .iter()
+            .rev()
+            .flatten()
+            .filter(|t| !t.is_empty() && (t.starts_with('-') || !n.starts_with('-')))
+            .filter(|t| seen.insert(t.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        fuzzy::rank(n, tokens, |t| t.as_str())
+            .into_iter()
+            .map(|t| Candidate::new(t, Source::History))
+            .collect()
+    }
+
+    // Only real options are offered for `-`/`--` input. For `--opt=value`
+    // the value part is completed from the documented choices and default.
+    fn option_candidates(&self, input: &str) -> Vec<Candidate> {
+        match split_value(input) {
+            (name, Some(value)) => {
+                let opt = match self.options.find(name) {
+                    Some(opt) => opt,
+                    None => return Vec::default(),
+                };
+                let mut values = opt.values.iter().collect::<Vec<_>>();
+                if let Some(default) = opt.default.as_ref().filter(|d| !values.contains(d)) {
+                    values.push(default);
+                }
+
+                fuzzy::rank(value, values, |v| v.as_str())
+                    .into_iter()
+                    .map(|v| Candidate::option(format!("{}={}", name, v), opt))
+                    .collect()
+            }
+            (name, None) => {
+                let mut seen = HashSet::new();
+                let names = self
+                    .options
+                    .iter()
+                    .flat_map(|opt| opt.names().into_iter().map(move |n| (n, opt)))
+                    .filter(|(n, _)| seen.insert(*n))
+                    .collect::<Vec<_>>();
+                let mut hits = fuzzy::rank(name, names, |(n, _)| n);
+
+                // `-` prefers short options, `--` can only match long ones.
+                let long = name.starts_with("--");
+                hits.sort_by_key(|(n, _)| n.starts_with("--") != long);
+                hits.into_iter()
+                    .map(|(n, opt)| Candidate::option(n.to_string(), opt))
+                    .collect()
+            }
+        }
+    }
+
+    // Tab accepts a lone candidate and otherwise opens the menu, or moves
+    // through it once it is open.
+    pub fn tab(&mut self) {
+        if let Some(menu) = &mut self.menu {
+            menu.next();
+            return;
+        }
+        if *self.get_mode() != PromptMode::Prompt {
+            return self.completion();
+        }
+
+        let candidates = self.candidates();
+        if candidates.len() > 1 {
+            self.menu = Some(Menu {
+                candidates,
+                selected: 0,
+            });
+        } else {
+            self.completion();
+        }
+    }
+
+    pub fn back_tab(&mut self) {
+        match &mut self.menu {
+            Some(menu) => menu.prev(),
+            None => {
+                let candidates = self.candidates();
+                if !candidates.is_empty() {
+                    self.menu = Some(Menu {
+                        selected: candidates.len() - 1,
+                        candidates,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn is_menu_open(&self) -> bool {
+        self.menu.is_some()
+    }
+
+    pub fn accept_menu(&mut self) {
+        if let Some(menu) = self.menu.take() {
+            let input = &mut self.argument[self.selected];
+            *input = menu.current().text.clone();
+            self.cursor = input.len();
+            self.completion = None;
+            self.set_literal();
+        }
+    }
+
+    // Closes whatever is open on top of the prompt. Returns false when
+    // there was nothing to close.
+    pub fn cancel(&mut self) -> bool {
+        self.bindings.take().is_some() || self.search.take().is_some() || self.menu.take().is_some()
+    }
+
+    // Esc closes the menu, the history search or the bindings first and
+    // quits, without accepting the line, otherwise. Returns true when it
+    // quit.
+    pub fn escape(&mut self) -> bool {
+        if self.cancel() {
+            return false;
+        }
+        self.quit();
+        true
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    // Opens the history search, or moves on to the next, older, match.
+    pub fn search_backward(&mut self) {
+        self.menu = None;
+        match &mut self.search {
+            Some(search) => {
+                if search.selected + 1 < search.matches.len() {
+                    search.selected += 1;
+                }
+            }
+            None => {
+                self.search = Some(Search::default());
+                self.filter_history();
+            }
+        }
+    }
+
+    pub fn search_forward(&mut self) {
+        match &mut self.search {
+            Some(search) => search.selected = search.selected.saturating_sub(1),
+            None => self.search_backward(),
+        }
+    }
+
+    // Ranks the history by fuzzy match with the query, the most recent
+    // first among equals. Repeated argument lists are listed once.
+    fn filter_history(&mut self) {
+        let query = match &self.search {
+            Some(search) => search.query.clone(),
+            None => return,
+        };
+        let mut seen = HashSet::new();
+        let mut matches = self
+            .histories
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, h)| seen.insert(*h))
+            .filter_map(|(i, h)| fuzzy::score(&query, &history_line(h)).map(|s| (Reverse(s), i)))
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|(s, _)| *s);
+
+        if let Some(search) = &mut self.search {
+            search.matches = matches.into_iter().map(|(_, i)| i).collect();
+            search.selected = 0;
+        }
+    }
+
+    // Replaces the arguments with the selected entry. The arguments are
+    // left as they were when nothing matches.
+    pub fn accept_search(&mut self) {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+
+        if let Some(i) = search.matches.get(search.selected) {
+            self.argument = self.histories[*i].clone();
+            self.literal = vec![true; self.argument.len()];
+            self.selected = self.argument.len() - 1;
+            self.cursor = 0;
+            self.reset_quote();
+            self.sync_subcommand();
+        }
+    }
+
+    pub fn toggle_bindings(&mut self, bindings: &[String]) {
+        self.bindings = match self.bindings {
+            Some(_) => None,
+            None => Some(bindings.to_vec()),
+        };
+    }
+
     pub fn completion(&mut self) {
         if let Some(comp) = &self.completion {
             match self.get_mode() {
@@ -496,146 +1173,406 @@ impl<T: Write + Send + Drop> Prompt<T> {
                 }
                 _ => {
                     let input = &mut self.argument[self.selected];
-                    input.push_str(&comp);
+                    *input = comp.clone();
                     self.cursor = input.len();
                 }
             }
+            self.set_literal();
             self.completion = None;
             self.pos = 0;
         }
     }
 
-    pub fn find_position(&self, buffer: &Vec<String>) -> Option<usize> {
+    // Options jump to the line defining them (or the best candidate's while
+    // the name is still incomplete) rather than their first mention, which
+    // is usually the SYNOPSIS. Anything else goes to the first literal
+    // occurrence and otherwise to the best fuzzy match.
+    pub fn find_position(&self) -> Option<usize> {
         let input = &self.argument[self.selected];
-        buffer.iter().position(|v| v.contains(input))
+        if input.starts_with('-') {
+            let definition = self
+                .options
+                .find(input)
+                .or_else(|| {
+                    let candidate = self.option_candidates(input).into_iter().next()?;
+                    self.options.find(&candidate.text)
+                })
+                .map(|opt| opt.line)
+                .filter(|line| *line < self.buffer.len());
+            if definition.is_some() {
+                return definition;
+            }
+        }
+
+        self.buffer
+            .iter()
+            .position(|v| v.contains(input))
+            .or_else(|| {
+                self.buffer
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| fuzzy::score(input, v).map(|s| (s, Reverse(i))))
+                    .max()
+                    .map(|(_, Reverse(i))| i)
+            })
     }
 
-    pub fn prompt() -> String {
+    // The definition of the last option before the current argument.
+    fn last_option_position(&self) -> Option<usize> {
+        self.argument[..self.selected]
+            .iter()
+            .rev()
+            .filter(|arg| arg.starts_with('-'))
+            .find_map(|arg| self.options.find(arg))
+            .map(|opt| opt.line)
+            .filter(|line| *line < self.buffer.len())
+    }
+
+    // Lines mentioning the current argument, for C-n/C-p. Fuzzy matches are
+    // only used when there is no literal one.
+    fn mentions(&self) -> Vec<usize> {
+        let input = &self.argument[self.selected];
+        if input.is_empty() {
+            return Vec::default();
+        }
+
+        let literal = self
+            .buffer
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.contains(input))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if !literal.is_empty() {
+            return literal;
+        }
+
+        self.buffer
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| fuzzy::score(input, line).is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    fn match_status(&self) -> Option<String> {
+        if let Some(search) = &self.search {
+            return match search.matches.len() {
+                0 => Some("no match".to_string()),
+                n => Some(format!("{}/{}", search.selected + 1, n)),
+            };
+        }
+        if self.loading {
+            return Some("loading…".to_string());
+        }
+        if *self.get_mode() != PromptMode::Prompt {
+            return None;
+        }
+
+        let mentions = self.mentions();
+        match mentions.iter().position(|n| *n == self.pos) {
+            _ if mentions.is_empty() => None,
+            Some(i) => Some(format!("match {}/{}", i + 1, mentions.len())),
+            None => Some(format!("{} matches", mentions.len())),
+        }
+    }
+
+    pub fn prompt(&self) -> String {
         format!(
             "{}{}{}",
             termion::style::Bold,
-            PROMPT,
+            self.config.prompt,
             termion::style::Reset
         )
     }
 
+    // Arguments are shown quoted the way they would be typed in a shell.
+    // The empty one after the last argument is where the next one goes.
+    fn display_argument(&self, i: usize) -> String {
+        let argument = &self.argument[i];
+        if i == self.selected && self.quote.is_some() {
+            shell::single_quote(argument)
+        } else if argument.is_empty() {
+            String::default()
+        } else if self.shell {
+            shell::word(argument, self.is_literal(i)).into_owned()
+        } else {
+            shell::quote(argument).into_owned()
+        }
+    }
+
+    fn display_cursor(&self) -> usize {
+        let argument = &self.argument[self.selected];
+        if self.display_argument(self.selected) == *argument {
+            self.cursor
+        } else {
+            1 + argument[..self.cursor].replace('\'', "'\\''").len()
+        }
+    }
+
     pub fn show_input(&mut self) -> Result<usize, std::io::Error> {
+        if let Some(search) = &self.search {
+            let p = format!(
+                "{prompt}{faint}{label}{reset}{query}",
+                prompt = self.prompt(),
+                faint = termion::style::Faint,
+                label = SEARCH,
+                reset = termion::style::Reset,
+                query = search.query
+            );
+            return self.stdout.write_all(p.as_bytes()).map(|_| p.len());
+        }
+
         let mut full_command = vec![self.command.clone()];
-        full_command.extend(self.argument.clone());
+        full_command.extend((0..self.argument.len()).map(|i| self.display_argument(i)));
 
         let p = match self.get_mode() {
             PromptMode::Prompt => {
                 format!(
                     "{prompt}{bold}{white}{command}{reset}",
-                    prompt = Self::prompt(),
+                    prompt = self.prompt(),
                     bold = termion::style::Bold,
-                    white = termion::color::Fg(termion::color::White),
+                    white = self.config.colors.command.fg(),
                     reset = termion::style::Reset,
                     command = full_command.join(" ")
                 )
-            }, 
+            }
             _ => {
                 format!(
                     "{prompt}{bold}{black}{command}{white}{command2}{reset}",
-                    prompt = Self::prompt(),
+                    prompt = self.prompt(),
                     bold = termion::style::Bold,
                     black = termion::color::Fg(termion::color::Black),
-                    white = termion::color::Fg(termion::color::White),
+                    white = self.config.colors.command.fg(),
                     reset = termion::style::Reset,
                     command = full_command.join(" "),
                     command2 = self.file_input
                 )
-            },
+            }
         };
-        self.stdout.write(p.as_bytes())
+        self.stdout.write_all(p.as_bytes()).map(|_| p.len())
     }
 
     fn prompt_len(&mut self) -> u64 {
         let mut full_command = vec![self.command.clone()];
-        let current = &self.argument[0..self.selected];
-        full_command.extend(current.to_vec());
+        full_command.extend((0..self.selected).map(|i| self.display_argument(i)));
 
-        PROMPT.len() as u64 + full_command.join(" ").len() as u64 + 1u64
+        self.config.prompt.width() as u64 + full_command.join(" ").len() as u64 + 1u64
     }
 
     pub fn incr_size(&mut self) {
         self.size += 1;
     }
 
+    // The panel keeps at least one line.
     pub fn decr_size(&mut self) {
-        self.size -= 1;
+        self.size = self.size.saturating_sub(1).max(1);
     }
 
-    pub fn show_candidate(&mut self) -> Option<String> {
-        if let Some(c) = self.candidates().first() {
-            let input = &self.argument[self.selected];
-            let comp = &c[input.len()..c.len()];
-
-            return Some(comp.to_string());
+    // Returns the text to show after the cursor and the candidate itself.
+    // Fuzzy matches that don't extend the input are shown in full.
+    pub fn show_candidate(&mut self) -> Option<(String, Candidate)> {
+        let c = match &self.menu {
+            Some(menu) => menu.current().clone(),
+            None => self.candidates().into_iter().next()?,
         };
-        None
+        let input = &self.argument[self.selected];
+        let hint = match c.text.strip_prefix(input.as_str()) {
+            Some(rest) => rest.to_string(),
+            None => format!(" {}", c.text),
+        };
+
+        Some((hint, c))
     }
 
     pub fn show_viewer(&mut self) -> Vec<String> {
         let (s, e) = self.viewpoint();
-        let mut buffer = self.viewer.show(self.buffer.clone());
+        let selected = match self.get_mode() {
+            PromptMode::Choose => self.choose_pos,
+            _ => self.pos,
+        };
 
-        match self.get_mode() {
-            PromptMode::Choose => {
-                let line = &self.buffer[self.choose_pos];
-                let decorated = format!(
-                    "{red}{input}{reset}",
-                    red = termion::color::Bg(termion::color::Red),
-                    input = line,
-                    reset = termion::style::Reset
-                );
-                buffer[self.choose_pos] = decorated;
-            }
-            PromptMode::File => {
-                let line = &self.buffer[self.pos];
-                let decorated = format!(
-                    "{red}{input}{reset}",
-                    red = termion::color::Bg(termion::color::Red),
-                    input = line,
-                    reset = termion::style::Reset
-                );
-                buffer[self.pos] = decorated;
-            }
-            _ => {
-                let input = &self.argument[self.selected];
-                let decorated = format!(
-                    "{red}{input}{reset}",
-                    red = termion::color::Fg(termion::color::Red),
-                    input = input,
-                    reset = termion::style::Reset
+        let lines = (s..e)
+            .map(|i| {
+                let line = &self.buffer[i];
+                let spans = self.styles.get(i).map(Vec::as_slice).unwrap_or_default();
+
+                let decorated = match self.get_mode() {
+                    PromptMode::Choose | PromptMode::File if i == selected => format!(
+                        "{red}{input}{reset}",
+                        red = self.config.colors.selected.bg(),
+                        input = line,
+                        reset = termion::style::Reset
+                    ),
+                    PromptMode::Prompt if i == selected => {
+                        let highlight = (
+                            self.argument[self.selected].as_str(),
+                            self.config.colors.highlight,
+                        );
+                        viewer::style(line, spans, Some(highlight))
+                    }
+                    _ => viewer::style(line, spans, None),
+                };
+                self.viewer.show(i, decorated)
+            })
+            .collect::<Vec<_>>();
+
+        for l in &lines {
+            self.stdout.write_all(l.as_bytes()).unwrap();
+
+            cursor::down(&mut self.stdout, 1);
+            cursor::horizon(&mut self.stdout, 1);
+        }
+
+        lines
+    }
+
+    fn source_label(&self, source: Source) -> &'static str {
+        match source {
+            Source::Page => self.viewer.source_name(),
+            Source::File => "file",
+            Source::History => "history",
+        }
+    }
+
+    // Draws the completion menu over the panel, keeping the highlighted
+    // entry in view.
+    pub fn show_menu(&mut self) -> Vec<String> {
+        let menu = match &self.menu {
+            Some(menu) => menu.clone(),
+            None => return Vec::default(),
+        };
+        let width = terminal_size()
+            .map(|(Width(w), _)| w as usize)
+            .unwrap_or(80);
+        let column = menu
+            .candidates
+            .iter()
+            .map(|c| c.text.chars().count())
+            .max()
+            .unwrap_or_default()
+            .min(width / 2);
+
+        let start = (menu.selected + 1).saturating_sub(self.size);
+        let lines = menu
+            .candidates
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(self.size)
+            .map(|(i, c)| {
+                let line = format!(
+                    "{text:<column$}  {source:<7} {description}",
+                    text = c.text,
+                    column = column,
+                    source = self.source_label(c.source),
+                    description = c.description.as_deref().unwrap_or_default()
                 );
-                buffer[self.pos] = buffer[self.pos].replace(input, &decorated);
-            }
+                let line = line.chars().take(width).collect::<String>();
+
+                if i == menu.selected {
+                    format!(
+                        "{red}{line}{reset}",
+                        red = self.config.colors.selected.bg(),
+                        line = line,
+                        reset = termion::style::Reset
+                    )
+                } else {
+                    line
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for l in &lines {
+            self.stdout.write_all(l.as_bytes()).unwrap();
+
+            cursor::down(&mut self.stdout, 1);
+            cursor::horizon(&mut self.stdout, 1);
         }
 
-        let lines = &buffer[s..e];
-        for l in lines {
-            self.stdout.write(l.as_bytes()).unwrap();
+        lines
+    }
+
+    // Lays the bindings out in as many columns as needed to fit the panel.
+    pub fn show_bindings(&mut self) -> Vec<String> {
+        let bindings = match &self.bindings {
+            Some(bindings) => bindings.clone(),
+            None => return Vec::default(),
+        };
+        let width = terminal_size()
+            .map(|(Width(w), _)| w as usize)
+            .unwrap_or(80);
+        let column = bindings
+            .iter()
+            .map(|b| b.chars().count() + 2)
+            .max()
+            .unwrap_or(1);
+
+        let rows = self.size.max(1);
+        let lines = (0..rows.min(bindings.len()))
+            .map(|row| {
+                let line = bindings
+                    .iter()
+                    .skip(row)
+                    .step_by(rows)
+                    .map(|b| format!("{:<column$}", b, column = column))
+                    .collect::<String>();
+                line.trim_end().chars().take(width).collect::<String>()
+            })
+            .collect::<Vec<_>>();
+
+        for l in &lines {
+            self.stdout.write_all(l.as_bytes()).unwrap();
 
             cursor::down(&mut self.stdout, 1);
             cursor::horizon(&mut self.stdout, 1);
         }
 
-        Vec::from(lines)
+        lines
     }
 
-    pub fn history_back(&mut self) {
-        if let Some(_) = self.history_path {
-            let hist = self.histories.iter().rev().collect::<Vec<_>>();
+    // Lists the matching entries, keeping the selected one in view.
+    pub fn show_search(&mut self) -> Vec<String> {
+        let search = match &self.search {
+            Some(search) => search.clone(),
+            None => return Vec::default(),
+        };
+        let width = terminal_size()
+            .map(|(Width(w), _)| w as usize)
+            .unwrap_or(80);
+
+        let start = (search.selected + 1).saturating_sub(self.size);
+        let lines = search
+            .matches
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(self.size)
+            .map(|(i, h)| {
+                let line = format!("{} {}", self.command, history_line(&self.histories[*h]));
+                let line = line.chars().take(width).collect::<String>();
+
+                if i == search.selected {
+                    format!(
+                        "{red}{line}{reset}",
+                        red = self.config.colors.selected.bg(),
+                        line = line,
+                        reset = termion::style::Reset
+                    )
+                } else {
+                    line
+                }
+            })
+            .collect::<Vec<_>>();
 
-            if let Some(hist) = hist.get(self.history_index as usize) {
-                self.selected = (*hist).len() - 1;
-                self.argument = (*hist).clone();
+        for l in &lines {
+            self.stdout.write_all(l.as_bytes()).unwrap();
 
-                self.cursor = 0;
-                self.history_index += 1;
-            }
+            cursor::down(&mut self.stdout, 1);
+            cursor::horizon(&mut self.stdout, 1);
         }
+
+        lines
     }
 
     pub fn show(&mut self) -> Result<(), failure::Error> {
@@ -643,34 +1580,74 @@ impl<T: Write + Send + Drop> Prompt<T> {
 
         let size = terminal_size();
 
-        if let Some(_) = size {
+        if size.is_some() {
             self.show_input()?;
             cursor::down(&mut self.stdout, 1);
-            cursor::horizon(&mut self.stdout, 1 as u64);
+            cursor::horizon(&mut self.stdout, 1);
 
-            let lines = self.show_viewer();
+            let lines = if self.bindings.is_some() {
+                self.show_bindings()
+            } else if self.search.is_some() {
+                self.show_search()
+            } else if self.menu.is_some() {
+                self.show_menu()
+            } else {
+                self.show_viewer()
+            };
             cursor::up(&mut self.stdout, lines.len() as u64);
 
             // Move cursor input position.
             cursor::up(&mut self.stdout, 1u64);
 
+            let width = size.map(|(Width(w), _)| w as usize).unwrap_or_default();
+            let status = self.match_status().unwrap_or_default();
+            let status_len = status.chars().count();
+            if status_len > 0 && status_len < width {
+                cursor::horizon(&mut self.stdout, (width - status_len + 1) as u64);
+                write!(
+                    self.stdout,
+                    "{faint}{status}{reset}",
+                    faint = termion::style::Faint,
+                    status = status,
+                    reset = termion::style::Reset
+                )?;
+            }
+
+            if let Some(search) = &self.search {
+                let l = self.config.prompt.width() + SEARCH.len() + search.query.width();
+                cursor::horizon(&mut self.stdout, l as u64 + 1);
+                return Ok(());
+            }
+
             let l = self.prompt_len();
-            cursor::horizon(&mut self.stdout, l + self.cursor as u64 + 1);
+            let l = l + self.display_cursor() as u64;
+            cursor::horizon(&mut self.stdout, l + 1);
 
-            if let Some(comp) = self.show_candidate() {
-                let s = format!(
+            if let Some((comp, candidate)) = self.show_candidate() {
+                let mut s = format!(
                     "{color}{comp}{reset}",
-                    color = termion::color::Fg(termion::color::Blue),
+                    color = self.config.colors.hint.fg(),
                     comp = comp,
                     reset = termion::style::Reset
                 );
 
-                cursor::horizon(&mut self.stdout, l + self.cursor as u64 + 1);
+                let room = width.saturating_sub(l as usize + comp.len() + status_len + 3);
+                if let Some(description) = candidate.description.filter(|_| room > 0) {
+                    let description = description.chars().take(room).collect::<String>();
+                    s.push_str(&format!(
+                        "  {faint}{description}{reset}",
+                        faint = termion::style::Faint,
+                        description = description,
+                        reset = termion::style::Reset
+                    ));
+                }
+
+                cursor::horizon(&mut self.stdout, l + 1);
 
-                self.stdout.write(s.as_bytes())?;
-                self.completion = Some(comp);
+                self.stdout.write_all(s.as_bytes())?;
+                self.completion = Some(candidate.text);
 
-                cursor::horizon(&mut self.stdout, l + self.cursor as u64 + 1);
+                cursor::horizon(&mut self.stdout, l + 1);
             }
         }
 
@@ -686,13 +1663,13 @@ impl<T: Write + Send + Drop> Prompt<T> {
         // input
         cursor::horizon(&mut self.stdout, 1);
         cursor::clear_line(&mut self.stdout);
-        self.stdout.write(b"\n")?;
+        self.stdout.write_all(b"\n")?;
 
         // panel
         for _ in 0..self.size {
             cursor::horizon(&mut self.stdout, 1);
             cursor::clear_line(&mut self.stdout);
-            self.stdout.write(b"\n")?;
+            self.stdout.write_all(b"\n")?;
         }
 
         cursor::horizon(&mut self.stdout, 1);
@@ -706,20 +1683,317 @@ impl<T: Write + Send + Drop> Prompt<T> {
     }
 }
 
+#[cfg(test)]
 mod test {
-    use termion::raw::{IntoRawMode, RawTerminal};
-
     use super::*;
 
     #[test]
     fn end_of_line() {
-        let stdout = std::io::stdout();
-        let stdout = stdout.into_raw_mode().unwrap();
-        let mut prompt = Prompt::new(stdout, &"diff".to_owned(), 10, false, false, None);
+        let stdout = Vec::new();
+        let mut prompt = Prompt::new(stdout, "diff", 10, false, false, None);
 
         prompt.argument.push("abc".to_string());
         prompt.end_of_line();
 
         assert_eq!(3, prompt.cursor)
     }
+
+    #[test]
+    fn option_candidates() {
+        let mut prompt = Prompt::new(Vec::new(), "ls", 10, false, true, None);
+        prompt.buffer = vec![
+            "  -a, --all          do not ignore entries. More text".to_string(),
+            "      --almost-all   do not list . and ..".to_string(),
+            "      --color <WHEN>  colorize [possible values: auto, always, never]".to_string(),
+            "  the --all flag".to_string(),
+        ];
+        prompt.options = Options::parse(&prompt.buffer);
+
+        prompt.argument = vec!["--al".to_string()];
+        prompt.cursor = 4;
+        let candidates = prompt.candidates();
+        assert_eq!(
+            vec!["--all", "--almost-all"],
+            candidates
+                .iter()
+                .map(|c| c.text.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some("do not ignore entries".to_string()),
+            candidates[0].description
+        );
+
+        prompt.argument = vec!["--color=a".to_string()];
+        prompt.cursor = 9;
+        let candidates = prompt.candidates();
+        assert_eq!(
+            vec!["--color=auto", "--color=always"],
+            candidates
+                .iter()
+                .map(|c| c.text.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn completion_menu() {
+        let mut prompt = Prompt::new(Vec::new(), "ls", 10, false, true, None);
+        prompt.buffer = vec![
+            "  -a, --all  all".to_string(),
+            "  --almost-all  almost".to_string(),
+        ];
+        prompt.options = Options::parse(&prompt.buffer);
+        prompt.histories = vec![vec!["--alpha".to_string(), String::new()]];
+
+        prompt.argument = vec!["--al".to_string()];
+        prompt.cursor = 4;
+        prompt.tab();
+        assert!(prompt.is_menu_open());
+
+        let menu = prompt.menu.clone().unwrap();
+        assert_eq!(Source::History, menu.candidates[2].source);
+
+        prompt.tab();
+        prompt.down();
+        prompt.up();
+        prompt.accept_menu();
+        assert!(!prompt.is_menu_open());
+        assert_eq!("--almost-all", prompt.argument[0]);
+        assert_eq!(12, prompt.cursor);
+    }
+
+    #[test]
+    fn escape_quits() {
+        let mut prompt = Prompt::new(Vec::new(), "rm", 10, false, true, None);
+        prompt.argument = vec!["-rf".to_string(), String::new()];
+        prompt.toggle_bindings(&["Esc  cancel".to_string()]);
+
+        assert!(!prompt.escape());
+        assert_eq!(
+            Some(("rm".to_string(), vec!["-rf".to_string()])),
+            prompt.accepted_command()
+        );
+
+        assert!(prompt.escape());
+        assert_eq!(None, prompt.accepted_command());
+    }
+
+    #[test]
+    fn history_search() {
+        let mut prompt = Prompt::new(Vec::new(), "ls", 10, false, true, None);
+        let history = |line: &str| {
+            let mut h = line.split(' ').map(ToString::to_string).collect::<Vec<_>>();
+            h.push(String::new());
+            h
+        };
+        prompt.histories = vec![
+            history("-l /tmp"),
+            history("-a"),
+            history("-l /tmp"),
+            history("-la /usr"),
+        ];
+        prompt.argument = vec!["-R".to_string(), String::new()];
+
+        prompt.search_backward();
+        assert_eq!(vec![3, 2, 1], prompt.search.clone().unwrap().matches);
+
+        "tmp".chars().for_each(|c| prompt.key(c));
+        assert_eq!(vec![2], prompt.search.clone().unwrap().matches);
+        prompt.backspace();
+        prompt.backspace();
+        prompt.backspace();
+        prompt.key('l');
+        prompt.search_backward();
+        prompt.search_backward();
+        assert_eq!(1, prompt.search.clone().unwrap().selected);
+        prompt.search_forward();
+        assert_eq!(0, prompt.search.clone().unwrap().selected);
+
+        assert!(prompt.cancel());
+        assert_eq!(vec!["-R", ""], prompt.argument);
+
+        prompt.search_backward();
+        prompt.key('u');
+        prompt.accept_search();
+        assert!(!prompt.is_searching());
+        assert_eq!(vec!["-la", "/usr", ""], prompt.argument);
+        assert_eq!(2, prompt.selected);
+    }
+
+    #[test]
+    fn subcommand_pages() {
+        let mut prompt = Prompt::new(
+            Vec::new(),
+            "man-with-no-such-command",
+            10,
+            false,
+            false,
+            None,
+        );
+        prompt.subcommands = vec![Subcommand {
+            name: "build".to_string(),
+            aliases: vec!["b".to_string()],
+            ..Subcommand::default()
+        }];
+
+        prompt.argument = vec!["b".to_string()];
+        prompt.cursor = 1;
+        prompt.append();
+        assert_eq!(Some("build"), prompt.viewer.subcommand());
+
+        prompt.backspace();
+        assert_eq!(Some("build"), prompt.viewer.subcommand());
+        prompt.backspace();
+        assert_eq!(vec![String::new()], prompt.argument);
+        assert_eq!(None, prompt.viewer.subcommand());
+
+        // Man pages are only looked for once per argument.
+        prompt.key('x');
+        prompt.append();
+        prompt.key('y');
+        prompt.select_back();
+        let key = ("man-with-no-such-command".to_string(), "x".to_string());
+        assert_eq!(Some(&false), prompt.subcommand_pages.get(&key));
+        assert_eq!(1, prompt.subcommand_pages.len());
+    }
+
+    #[test]
+    fn background_loading() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut prompt = Prompt::new(
+            Vec::new(),
+            "man-with-no-such-command",
+            10,
+            false,
+            false,
+            None,
+        );
+        prompt.set_loader(tx);
+        prompt.load();
+        assert_eq!(Some("loading…".to_string()), prompt.match_status());
+
+        // A newer load supersedes whatever the first one still sends.
+        prompt.load();
+        for event in rx.iter().take(4) {
+            match event {
+                Event::Source(generation, lines) => prompt.receive(generation, lines),
+                Event::Loaded(generation, help) => prompt.loaded(generation, help),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(None, prompt.match_status());
+        assert_eq!(vec![String::new()], prompt.buffer);
+    }
+
+    #[test]
+    fn preloaded_arguments() {
+        let mut prompt = Prompt::new(
+            Vec::new(),
+            "man-with-no-such-command",
+            10,
+            false,
+            false,
+            None,
+        );
+        prompt.preload(vec![("-x".to_string(), false), ("-f".to_string(), false)]);
+        assert_eq!(2, prompt.selected);
+        assert_eq!(0, prompt.cursor);
+
+        let help = Help::parse(&[
+            "OPTIONS".to_string(),
+            "  -f FILE   archive file".to_string(),
+            "  -x        extract".to_string(),
+        ]);
+        prompt.generation += 1;
+        prompt.buffer = vec![String::default(); 3];
+        prompt.loaded(prompt.generation, help);
+        assert_eq!(1, prompt.pos);
+
+        for _ in 0..20 {
+            prompt.decr_size();
+        }
+        assert_eq!(1, prompt.size);
+    }
+
+    #[test]
+    fn quoted_input() {
+        let mut prompt = Prompt::new(Vec::new(), "git", 10, false, true, None);
+        prompt.insert_line(String::default());
+        for ch in "commit -m \"it's done\" a\\ b".chars() {
+            prompt.key(ch);
+        }
+
+        assert_eq!(vec!["commit", "-m", "it's done", "a b"], prompt.argument);
+        assert_eq!("'it'\\''s done'", prompt.display_argument(2));
+
+        prompt.append();
+        prompt.key('\'');
+        assert_eq!("''", prompt.display_argument(4));
+        prompt.backspace();
+        assert_eq!("", prompt.display_argument(4));
+
+        // Run by the shell, typed words stay as they were typed.
+        let mut prompt = Prompt::new(Vec::new(), "ls", 10, false, true, None);
+        prompt.set_shell(true);
+        prompt.insert_line(String::default());
+        for ch in "~/src '*.rs' $HOME 2>&1".chars() {
+            prompt.key(ch);
+        }
+        assert_eq!("'*.rs'", prompt.display_argument(1));
+        assert_eq!("ls ~/src '*.rs' $HOME 2>&1", prompt.shell_line());
+    }
+
+    #[test]
+    fn pipeline_segments() {
+        let mut prompt = Prompt::new(
+            Vec::new(),
+            "man-with-no-such-command",
+            10,
+            false,
+            false,
+            None,
+        );
+        prompt.set_shell(true);
+        for ch in "-l | man-with-other-command -x".chars() {
+            prompt.key(ch);
+        }
+        assert_eq!("man-with-other-command", prompt.viewer.command());
+
+        prompt.select_back();
+        prompt.select_back();
+        prompt.select_back();
+        assert_eq!("man-with-no-such-command", prompt.viewer.command());
+
+        prompt.set_shell(false);
+        prompt.end_of_line();
+        assert_eq!("man-with-no-such-command", prompt.viewer.command());
+    }
+
+    #[test]
+    fn jump_to_definition() {
+        let mut prompt = Prompt::new(Vec::new(), "ls", 10, false, true, None);
+        prompt.buffer = vec![
+            "SYNOPSIS".to_string(),
+            "       ls [--color[=WHEN]]".to_string(),
+            "OPTIONS".to_string(),
+            "       --color[=WHEN]".to_string(),
+            "              colorize the output".to_string(),
+            "       -l     long listing, see --color".to_string(),
+        ];
+        prompt.options = Options::parse(&prompt.buffer);
+
+        for ch in "--col".chars() {
+            prompt.insert(ch);
+        }
+        assert_eq!(3, prompt.pos);
+        assert_eq!(Some("match 2/3".to_string()), prompt.match_status());
+
+        prompt.next();
+        assert_eq!(5, prompt.pos);
+        prompt.next();
+        assert_eq!(1, prompt.pos);
+        prompt.prev();
+        assert_eq!(5, prompt.pos);
+    }
 }
@@ -1,10 +1,19 @@
+use std::path::PathBuf;
 use std::process::Command;
 
+use terminal_size::{terminal_size, Width};
+
+use crate::cache::{self, Cache, Entry, Key};
+use crate::config::Color;
+use crate::help::Help;
+use crate::man::{self, overstrike, Font, Line, Span};
+
 #[derive(Clone)]
 pub enum SourceType {
     Man,
     Help,
     Stdin,
+    #[allow(dead_code)]
     File,
 }
 
@@ -19,6 +28,7 @@ pub struct Viewer {
     source_type: SourceType,
     show_type: ShowType,
     command: String,
+    subcommand: Option<String>,
 }
 
 impl Viewer {
@@ -27,26 +37,107 @@ impl Viewer {
             source_type,
             show_type: ShowType::Normal,
             command: String::from(command),
+            subcommand: None,
+        }
+    }
+
+    pub fn source(&self) -> Vec<Line> {
+        let lines = match self.source_type {
+            // Subcommands without a page of their own fall back to `--help`.
+            SourceType::Man => match self.man() {
+                ref lines if lines.is_empty() && self.subcommand.is_some() => decode(&self.help()),
+                lines => lines,
+            },
+            SourceType::Help => decode(&self.help()),
+            SourceType::File => decode(&self.file_path(None)),
+            SourceType::Stdin => Vec::default(),
+        };
+
+        if lines.is_empty() {
+            decode("")
+        } else {
+            lines
+        }
+    }
+
+    // The rendered page and its parsed options, from the on-disk cache when
+    // the page was rendered before from the same man file or binary.
+    pub fn load(&self) -> (Vec<Line>, Help) {
+        let key = self.cache_key();
+        let cache = key.as_ref().and_then(|_| Cache::open());
+        if let (Some(key), Some(cache)) = (&key, &cache) {
+            if let Some(entry) = cache.read(key) {
+                return (entry.lines, entry.help);
+            }
         }
+
+        let lines = self.source();
+        let help = Help::parse(&lines.iter().map(|l| l.text.clone()).collect::<Vec<_>>());
+        if let (Some(key), Some(cache)) = (key, cache) {
+            let entry = Entry { key, lines, help };
+            let _ = cache.write(&entry);
+            return (entry.lines, entry.help);
+        }
+
+        (lines, help)
     }
 
-    pub fn source(&self) -> String {
+    fn cache_key(&self) -> Option<Key> {
+        let (path, width) = match self.source_type {
+            SourceType::Man => match man::find(&self.page()) {
+                Some(path) => (path, width()),
+                None if self.subcommand.is_some() => (cache::which(&self.command)?, 0),
+                None => return None,
+            },
+            SourceType::Help => (cache::which(&self.command)?, 0),
+            SourceType::Stdin | SourceType::File => return None,
+        };
+
+        Key::new(self.source_name(), &self.page(), width, &path)
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        matches!(self.source_type, SourceType::Stdin)
+    }
+
+    pub fn is_help(&self) -> bool {
+        matches!(self.source_type, SourceType::Help)
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn subcommand(&self) -> Option<&str> {
+        self.subcommand.as_deref()
+    }
+
+    pub fn set_command(&mut self, command: &str, subcommand: Option<String>) {
+        self.command = String::from(command);
+        self.subcommand = subcommand;
+    }
+
+    // `git commit` is documented in git-commit(1).
+    fn page(&self) -> String {
+        match &self.subcommand {
+            Some(sub) => format!("{}-{}", self.command, sub),
+            None => self.command.clone(),
+        }
+    }
+
+    pub fn source_name(&self) -> &'static str {
         match self.source_type {
-            SourceType::Man => self.man(),
-            SourceType::Help => self.help(),
-            SourceType::File => self.file_path(None),
-            SourceType::Stdin => String::default(),
+            SourceType::Man => "man",
+            SourceType::Help => "help",
+            SourceType::Stdin => "stdin",
+            SourceType::File => "file",
         }
     }
 
-    pub fn show(&self, buffer: Vec<String>) -> Vec<String> {
+    pub fn show(&self, index: usize, line: String) -> String {
         match self.show_type {
-            ShowType::LineNumber => buffer
-                .iter()
-                .enumerate()
-                .map(|(i, line)| format!("{number} {line}", number = i + 1, line = line))
-                .collect::<Vec<_>>(),
-            _ => buffer,
+            ShowType::LineNumber => format!("{number} {line}", number = index + 1, line = line),
+            _ => line,
         }
     }
 
@@ -58,41 +149,111 @@ impl Viewer {
         }
     }
 
-    fn man(&self) -> String {
-        let cmd = Command::new("sh")
-            .arg("-c")
-            .arg(format!("man {} | col -bx", self.command))
-            .output()
-            .expect("failed to execute process");
-        let out = cmd.stdout;
-
-        String::from_utf8_lossy(&out).to_string()
+    fn man(&self) -> Vec<Line> {
+        man::render(&self.page(), width())
+            .map(|doc| doc.lines)
+            .unwrap_or_default()
     }
 
     fn help(&self) -> String {
-        let cmd = Command::new(self.command.clone())
+        let cmd = match Command::new(self.command.clone())
+            .args(self.subcommand.iter())
             .arg("--help")
             .output()
-            .expect("failed to execute process");
-        let out = cmd.stdout;
+        {
+            Ok(cmd) => cmd,
+            Err(_) => return String::default(),
+        };
+        // Go's flag package prints its usage to stderr.
+        let out = if cmd.stdout.is_empty() {
+            cmd.stderr
+        } else {
+            cmd.stdout
+        };
 
         String::from_utf8_lossy(&out).to_string()
     }
 
-    pub fn file_path(&self, path: Option<std::path::PathBuf>) -> String {
+    pub fn file_path(&self, path: Option<PathBuf>) -> String {
+        self.files(path).join("\n")
+    }
+
+    pub fn files(&self, path: Option<PathBuf>) -> Vec<String> {
         let mut result = Vec::default();
+        self.walk_files(path, |p| {
+            result.push(p);
+            true
+        });
+        result
+    }
 
-        let path = if let Some(base) = path {
-            base
-        } else {
-            std::env::current_dir().unwrap_or(std::path::PathBuf::from("."))
-        };
+    // Walks the tree below `path` (the current directory by default) until
+    // `f` returns false.
+    pub fn walk_files<F>(&self, path: Option<PathBuf>, mut f: F)
+    where
+        F: FnMut(String) -> bool,
+    {
+        let path = path.unwrap_or_else(|| std::env::current_dir().unwrap_or(PathBuf::from(".")));
 
-        for entry in walkdir::WalkDir::new(path) {
-            let entry = entry.unwrap();
-            result.push(entry.path().display().to_string());
+        for entry in walkdir::WalkDir::new(path).into_iter().flatten() {
+            if !f(entry.path().display().to_string()) {
+                break;
+            }
         }
+    }
+}
+
+fn width() -> usize {
+    terminal_size()
+        .map(|(Width(w), _)| w as usize)
+        .unwrap_or(80)
+}
+
+fn decode(text: &str) -> Vec<Line> {
+    text.split('\n').map(overstrike::decode).collect()
+}
+
+// Renders bold as bold and italic as underline like a terminal man(1)
+// does, with occurrences of `highlight` drawn in its color on top.
+pub fn style(text: &str, spans: &[Span], highlight: Option<(&str, Color)>) -> String {
+    let color = highlight.map(|(_, c)| c.fg()).unwrap_or_default();
+    let marks = highlight
+        .map(|(h, _)| h)
+        .filter(|h| !h.is_empty())
+        .map(|h| {
+            text.match_indices(h)
+                .map(|(i, m)| (i, i + m.len()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut out = String::with_capacity(text.len());
+    let mut state = (Font::Regular, false);
+    for (i, c) in text.char_indices() {
+        let font = spans
+            .iter()
+            .find(|s| s.start <= i && i < s.end)
+            .map(|s| s.font)
+            .unwrap_or(Font::Regular);
+        let marked = marks.iter().any(|(s, e)| *s <= i && i < *e);
+
+        if (font, marked) != state {
+            out.push_str(termion::style::Reset.as_ref());
+            match font {
+                Font::Bold => out.push_str(termion::style::Bold.as_ref()),
+                Font::Italic => out.push_str(termion::style::Underline.as_ref()),
+                Font::Regular => {}
+            }
+            if marked {
+                out.push_str(&color);
+            }
+            state = (font, marked);
+        }
+        out.push(c);
+    }
 
-        result.join("\n")
+    if state != (Font::Regular, false) {
+        out.push_str(termion::style::Reset.as_ref());
     }
+    out
 }